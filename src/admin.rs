@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::commands::Command;
+use crate::config::{self, FeatureKey};
+use crate::irc::Context;
+use crate::message_queue::Priority;
+use crate::permission::Permission;
+use crate::twitch::TwitchMessage;
+
+/// Runtime config changes from chat: `!bot join|part #channel` and `!bot enable|disable <feature> [#channel]`,
+/// plus `!bot queue` to inspect the outbound queue, `!bot resume #channel` after an unban
+/// and `!bot sync` to reconcile the armory with the gateway
+pub struct Bot;
+
+#[async_trait]
+impl Command for Bot {
+    fn name(&self) -> &str {
+        "!bot"
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Any
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Owner
+    }
+
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let current = message.channel.as_str();
+        let reply = match run(ctx, current, args).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                log::error!("{}: admin command {:?} failed: {}", current, args, e);
+                format!("[💚] Failed: {}", e)
+            }
+        };
+        ctx.reply_with_priority(message, reply.as_str(), Priority::System).await?;
+        Ok(false)
+    }
+}
+
+async fn run(ctx: &Context, current: &str, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    match args {
+        ["join", channel] => {
+            let channel = channel_name(channel);
+            ctx.edit_config(|config| {
+                config.channel_mut(&channel).active = true;
+                Ok(())
+            })?;
+            Ok(format!("[💚] Joined {}", channel))
+        },
+        ["part", channel] => {
+            let channel = channel_name(channel);
+            ctx.edit_config(|config| {
+                config.channels.iter_mut()
+                    .find(|c| c.name == channel)
+                    .ok_or(format!("Unknown channel {}", channel))?
+                    .active = false;
+                Ok(())
+            })?;
+            Ok(format!("[💚] Parted {}", channel))
+        },
+        ["enable", feature, rest @ ..] | ["disable", feature, rest @ ..] if rest.len() <= 1 => {
+            let key = config::parse_feature(feature);
+            if let FeatureKey::Unknown(_) = key {
+                return Err(format!("Unknown feature {}", feature).into());
+            }
+            let channel = rest.first().map(|c| channel_name(c)).unwrap_or(current.to_owned());
+            let enable = args[0] == "enable";
+            ctx.edit_config(|config| {
+                let channel = config.channel_mut(&channel);
+                if enable {
+                    channel.enable(key);
+                } else {
+                    channel.disable(key);
+                }
+                Ok(())
+            })?;
+            Ok(format!("[💚] {} {} in {}", if enable {"Enabled"} else {"Disabled"}, feature, channel))
+        },
+        ["queue"] => {
+            let stats = ctx.queue_stats().ok_or("No outbound queue")?;
+            let mut failures: Vec<String> = stats.failures.iter()
+                .map(|(reason, count)| format!("{} {}", count, reason))
+                .collect();
+            failures.sort();
+            Ok(format!("[💚] Queued {} system, {} replies, {} ambient; oldest {}ms, average wait {}ms, {} sent, {} dropped; \
+                rejected: {}; paused: {}",
+                stats.queued[0], stats.queued[1], stats.queued[2],
+                stats.oldest.as_millis(), stats.average_wait.as_millis(), stats.sent, stats.dropped,
+                if failures.is_empty() { "none".to_owned() } else { failures.join(", ") },
+                if stats.paused.is_empty() { "none".to_owned() } else { stats.paused.join(", ") }))
+        },
+        ["sync"] => {
+            let status = match ctx.swords.sync(Arc::clone(&ctx.armory)).await {
+                Ok(status) => status,
+                Err(_) => ctx.swords.sync_status()
+            };
+            let last = status.last_success
+                .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or("never".to_owned());
+            Ok(format!("[💚] Armory {}: {} swords in the gateway, {} waiting for an id, last synced {}{}",
+                if status.synced { "synced" } else { "out of sync" },
+                status.remote, status.unsynced, last,
+                status.last_error.map(|e| format!(", error: {}", e)).unwrap_or_default()))
+        },
+        ["resume", channel] => {
+            let channel = channel_name(channel);
+            if ctx.resume(&channel) {
+                Ok(format!("[💚] Resumed {}", channel))
+            } else {
+                Ok(format!("[💚] {} was not paused", channel))
+            }
+        },
+        _ => Ok("[💚] Usage: !bot join|part #channel, !bot enable|disable <feature> [#channel], !bot queue, !bot resume #channel, !bot sync".to_owned())
+    }
+}
+
+fn channel_name(channel: &str) -> String {
+    let channel = channel.to_lowercase();
+    if channel.starts_with('#') {
+        channel
+    } else {
+        format!("#{}", channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shutdown::Shutdown;
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn owner_joins_and_toggles_features() {
+        let (server, config_path, handle) = test_support::start_bot("admin", &[("#test", &["ping"])], Shutdown::manual().1).await;
+        server.wait_for(0, |l| l == "JOIN #test").await.expect("Channel was not joined");
+
+        server.privmsg("#test", "owner", "!bot join other", &[("id", "1")]);
+        server.wait_for(0, |l| l == "JOIN #other").await.expect("Channel was not joined");
+        server.wait_for(0, |l| l.ends_with("Joined #other")).await.expect("No reply sent");
+
+        server.privmsg("#test", "owner", "!bot disable ping", &[("id", "2")]);
+        server.wait_for(0, |l| l.ends_with("Disabled ping in #test")).await.expect("No reply sent");
+        let config = crate::config::from_json(&config_path).expect("Config was not saved");
+        let test = config.channels.iter().find(|c| c.name == "#test").expect("Channel was dropped");
+        assert!(test.features.is_empty());
+        assert!(config.channels.iter().any(|c| c.name == "#other" && c.active));
+
+        server.privmsg("#test", "owner", "!bot enable nonsense", &[("id", "3")]);
+        server.wait_for(0, |l| l.ends_with("Failed: Unknown feature nonsense")).await.expect("No reply sent");
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn ignores_everyone_but_owners() {
+        let (server, _, handle) = test_support::start_bot("admin-denied", &[("#test", &[])], Shutdown::manual().1).await;
+        server.wait_for(0, |l| l == "JOIN #test").await.expect("Channel was not joined");
+
+        server.privmsg("#test", "chatter", "!bot part #test", &[("id", "1"), ("badges", "broadcaster/1")]);
+        server.privmsg("#test", "owner", "!bot", &[("id", "2")]);
+        server.wait_for(0, |l| l.contains("Usage: !bot")).await.expect("No reply sent");
+        let received = server.received().await;
+        assert!(!received.iter().any(|l| l == "PART #test" || l.contains("Parted")));
+        handle.abort();
+    }
+}
@@ -1,60 +1,239 @@
 use std::fmt;
 use std::error::Error;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::io::{BufReader, BufRead};
 use std::fs::File;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use cruet::to_title_case;
 use tokio::sync::RwLock;
+use tokio::task::JoinSet;
 use json::object;
 use rand::{
     distr::{Distribution, StandardUniform},
     seq::IndexedRandom,
     Rng
 };
-use crate::gateway;
+use crate::chatters::Chatter;
+use crate::gateway::GatewayError;
+use crate::store::ArmoryStore;
+use crate::outbox::Outbox;
 
 const LANG_SIZE: usize = 2222;
+/// How often swords the store didn't take are posted again
+const OUTBOX_RETRY: Duration = Duration::from_secs(60);
+/// How often the cache is reconciled with the store
+const SYNC_INTERVAL: Duration = Duration::from_secs(600);
+
+/// How the cache compares to the store
+#[derive(Debug, Clone, Default)]
+pub struct SyncStatus {
+    /// The last sync succeeded
+    pub synced: bool,
+    pub last_success: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_error: Option<String>,
+    /// Swords the store had at the last sync
+    pub remote: usize,
+    /// Local swords still waiting for an id
+    pub unsynced: usize
+}
 
 pub struct Swords {
-    cache: Arc<RwLock<Vec<Sword>>>,
-    cache_synced: bool,
-    elven: PathBuf
+    cache: Arc<RwLock<Cache>>,
+    sync: Arc<Mutex<SyncStatus>>,
+    elven: PathBuf,
+    /// Swords still being posted to the store
+    pending: Mutex<JoinSet<()>>,
+    outbox: Arc<Outbox>
 }
 
 impl Swords {
-    pub async fn new(elven: PathBuf, gateway: Arc<gateway::Gateway>) -> Result<Self, Box<dyn Error + Send + Sync>> {
-        let mut cache_synced = false;
-        let cache = match Self::init_cache(gateway).await {
-            Ok(cache) => {
-                cache_synced = true;
-                cache
-            },
+    pub async fn new(
+        elven: PathBuf,
+        outbox: PathBuf,
+        store: Arc<dyn ArmoryStore>
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let outbox = Arc::new(Outbox::open(outbox)?);
+        let mut cache = Vec::new();
+        for entry in outbox.pending() {
+            match Sword::from_json(&entry.body, None) {
+                Ok(mut sword) => {
+                    sword.client_id = Some(entry.key);
+                    cache.push(sword);
+                },
+                Err(e) => log::error!("Unreadable sword in the outbox {}: {}", entry.key, e)
+            }
+        }
+        let swords = Self {
+            elven,
+            cache: Arc::new(RwLock::new(Cache::new(cache))),
+            sync: Arc::new(Mutex::new(SyncStatus::default())),
+            pending: Mutex::new(JoinSet::new()),
+            outbox
+        };
+        if let Err(e) = swords.sync(Arc::clone(&store)).await {
+            log::error!("Failed to initialize cache: {}", e);
+            log::warn!("Continuing without local cache...");
+        }
+
+        tokio::spawn(Self::retry_outbox(Arc::clone(&swords.outbox), Arc::clone(&swords.cache), Arc::clone(&store)));
+        let (cache, outbox, sync) = (Arc::clone(&swords.cache), Arc::clone(&swords.outbox), Arc::clone(&swords.sync));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SYNC_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = Self::reconcile(&cache, &outbox, &sync, Arc::clone(&store)).await {
+                    log::error!("Failed to sync the armory: {}", e);
+                }
+            }
+        });
+        Ok(swords)
+    }
+
+    /// Pull the whole armory from the store and merge it into the cache now
+    pub async fn sync(&self, store: Arc<dyn ArmoryStore>) -> Result<SyncStatus, String> {
+        Self::reconcile(&self.cache, &self.outbox, &self.sync, store).await?;
+        Ok(self.sync_status())
+    }
+
+    pub fn sync_status(&self) -> SyncStatus {
+        self.sync.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// The store wins for every sword that has an id, so renames and deletions made elsewhere show up.
+    /// Swords still waiting for an id are kept, unless the store already holds one posted under their key
+    async fn reconcile(
+        cache: &RwLock<Cache>,
+        outbox: &Outbox,
+        sync: &Mutex<SyncStatus>,
+        store: Arc<dyn ArmoryStore>
+    ) -> Result<(), String> {
+        let remote = match Self::init_cache(store).await.map_err(|e| e.to_string()) {
+            Ok(remote) => remote,
             Err(e) => {
-                log::error!("Failed to initialize cache: {}", e);
-                log::warn!("Continuing without local cache...");
-                Vec::new()
+                let mut sync = sync.lock().unwrap_or_else(|e| e.into_inner());
+                sync.synced = false;
+                sync.last_error = Some(e.clone());
+                return Err(e);
             }
         };
-        Ok(Self {
-            elven,
-            cache: Arc::new(RwLock::new(cache)),
-            cache_synced,
+
+        let mut cache = cache.write().await;
+        let known: HashSet<i64> = cache.iter().filter_map(|s| s.id).collect();
+        let newest_remote = remote.iter().filter_map(|s| s.id).max().unwrap_or(-1);
+        // The store keeps the outbox key a sword was posted under, only that ties a remote sword to our write
+        let posted: HashMap<String, i64> = remote.iter()
+            .filter_map(|s| Some((s.client_id.clone()?, s.id?)))
+            .collect();
+        let mut merged = remote;
+        let remote_count = merged.len();
+        // Swords posted while the pages were downloading are newer than anything remote
+        for local in cache.take().into_iter().filter(|s| !matches!(s.id, Some(id) if id <= newest_remote)) {
+            let (None, Some(key)) = (local.id, &local.client_id) else {
+                merged.push(local);
+                continue;
+            };
+            let Some(id) = posted.get(key) else {
+                // A parked post never landed and may go again, the key keeps a late arrival from doubling it
+                if outbox.try_claim(key) {
+                    outbox.release(key);
+                }
+                merged.push(local);
+                continue;
+            };
+            // The remote sword stands in for ours, whoever is still posting it acknowledges the write when done
+            if outbox.try_claim(key) {
+                log::info!("Sword {} of {} already has id {}", local, local.owner, id);
+                if let Err(e) = outbox.done(key) {
+                    log::error!("Failed to journal armory write {}: {}", key, e);
+                }
+            }
+        }
+        let unsynced = merged.iter().filter(|s| s.id.is_none()).count();
+        let removed = known.iter().filter(|id| !merged.iter().any(|s| s.id == Some(**id))).count();
+        if removed > 0 {
+            log::info!("{} swords were removed from the armory", removed);
+        }
+        cache.replace(merged);
+
+        let mut sync = sync.lock().unwrap_or_else(|e| e.into_inner());
+        *sync = SyncStatus {
+            synced: true,
+            last_success: Some(chrono::Utc::now()),
+            last_error: None,
+            remote: remote_count,
+            unsynced
+        };
+        Ok(())
+    }
+
+    /// Post journaled swords until the store takes them
+    async fn retry_outbox(outbox: Arc<Outbox>, cache: Arc<RwLock<Cache>>, store: Arc<dyn ArmoryStore>) {
+        let mut interval = tokio::time::interval(OUTBOX_RETRY);
+        loop {
+            interval.tick().await;
+            Self::repost(&outbox, &cache, store.as_ref()).await;
+        }
+    }
+
+    async fn repost(outbox: &Outbox, cache: &RwLock<Cache>, store: &dyn ArmoryStore) {
+        for entry in outbox.claim() {
+            log::info!("Retrying armory write {}", entry.key);
+            match Self::post(store, Some(&entry.key), entry.body).await {
+                Ok(id) => Self::acknowledge(outbox, cache, &entry.key, id).await,
+                Err(e) => Self::put_back(outbox, &entry.key, &*e)
+            }
+        }
+    }
+
+    /// Id the store gave a sword. The outbox key goes along as `client_id`,
+    /// so a post repeated after a lost answer gets the id of the sword already stored instead of a copy
+    async fn post(store: &dyn ArmoryStore, key: Option<&str>, mut body: json::JsonValue) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        if let Some(key) = key {
+            body["client_id"] = key.into();
+        }
+        store.create(body).await.map_err(|e| {
+            log::error!("Failed to post a sword: {}", e);
+            e
         })
     }
 
-    async fn init_cache(gateway: Arc<gateway::Gateway>) -> Result<Vec<Sword>, Box<dyn Error + Send + Sync>> {
+    /// Hand a failed write back to `retry_outbox` only when the gateway would retry the post itself.
+    /// Anything else may have been stored, so it waits for a sync to look for it. Other stores fail before writing
+    fn put_back(outbox: &Outbox, key: &str, e: &(dyn Error + Send + Sync + 'static)) {
+        match e.downcast_ref::<GatewayError>() {
+            Some(e) if !e.retryable(false) => {
+                log::warn!("Armory write {} may have been stored, waiting for a sync to tell", key);
+                outbox.park(key);
+            },
+            _ => outbox.release(key)
+        }
+    }
+
+    /// Mark the write done and give the cached sword its id
+    async fn acknowledge(outbox: &Outbox, cache: &RwLock<Cache>, key: &str, id: i64) {
+        if let Err(e) = outbox.done(key) {
+            log::error!("Failed to journal armory write {}: {}", key, e);
+        }
+        let mut cache = cache.write().await;
+        if let Some(sword) = cache.iter_mut().find(|s| s.id.is_none() && s.client_id.as_deref() == Some(key)) {
+            sword.set_id(id);
+        }
+    }
+
+    async fn init_cache(store: Arc<dyn ArmoryStore>) -> Result<Vec<Sword>, Box<dyn Error + Send + Sync>> {
         let mut total = Vec::new();
         let mut page = 1;
         loop {
-            page += 1;
-            let (mut swords, has_next) = Self::get_swords(page, Arc::clone(&gateway)).await?;
+            let (mut swords, has_next) = Self::get_swords(page, Arc::clone(&store)).await?;
             total.append(&mut swords);
             if !has_next {
                 break
             }
+            page += 1;
         }
         Ok(total)
     }
@@ -87,7 +266,9 @@ impl Swords {
             sword_type,
             name: None,
             real_name: None,
-            handle, quality, owner: owner.clone()
+            handle, quality, owner: owner.clone(),
+            history: Vec::new(),
+            client_id: None
         }
     }
 
@@ -100,50 +281,65 @@ impl Swords {
         Ok(true)
     }
 
-    pub async fn log(&self, sword: Sword, gateway: Arc<gateway::Gateway>) {
-        self.post_sword(&sword, gateway);
+    pub async fn log(&self, sword: Sword, store: Arc<dyn ArmoryStore>) {
+        self.post_sword(&sword, store);
     }
 
-    fn post_sword(&self, sword: &Sword, gateway: Arc<gateway::Gateway>) {
-        let gateway = Arc::clone(&gateway);
+    fn post_sword(&self, sword: &Sword, store: Arc<dyn ArmoryStore>) {
+        let store = Arc::clone(&store);
         let cache = Arc::clone(&self.cache);
+        let outbox = Arc::clone(&self.outbox);
         let mut sword = sword.clone();
-        tokio::spawn(
+        match outbox.push(sword.serialize()) {
+            Ok(key) => sword.client_id = Some(key),
+            Err(e) => log::error!("Failed to journal sword {}, it won't survive a restart: {}", sword, e)
+        }
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        while pending.try_join_next().is_some() {}
+        pending.spawn(
             async move {
-                if let Ok(Some(Some(id))) = gateway.post("/armory", sword.serialize()).await.map(|v| v.map(|v| v["id"].as_i64())) {
-                    sword.set_id(id)
-                } else {
-                    log::warn!("Failed to bestow an id onto a sword {}", sword)
+                let key = sword.client_id.clone();
+                cache.write().await.push(sword.clone());
+                match (Self::post(store.as_ref(), key.as_deref(), sword.serialize()).await, key) {
+                    (Ok(id), Some(key)) => Self::acknowledge(&outbox, &cache, &key, id).await,
+                    (Ok(id), None) => {
+                        let mut cache = cache.write().await;
+                        if let Some(cached) = cache.iter_mut().find(|s| s.id.is_none() && **s == sword && s.owner == sword.owner) {
+                            cached.set_id(id);
+                        }
+                    },
+                    (Err(e), Some(key)) => {
+                        log::warn!("Failed to bestow an id onto a sword {}, will retry", sword);
+                        Self::put_back(&outbox, &key, &*e);
+                    },
+                    (Err(_), None) => log::warn!("Failed to bestow an id onto a sword {}", sword)
                 }
-                cache.write().await.push(sword);
             }
         );
     }
 
+    /// Wait for swords still being posted to the gateway, giving up after `timeout`
+    pub async fn flush(&self, timeout: Duration) {
+        let mut pending = std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+        if pending.is_empty() {
+            return;
+        }
+        log::info!("Waiting for {} swords to be saved", pending.len());
+        let finished = tokio::time::timeout(timeout, async {
+            while pending.join_next().await.is_some() {}
+        }).await;
+        if finished.is_err() {
+            log::error!("Gave up on saving {} swords", pending.len());
+        }
+    }
+
     async fn get_swords(
         page: u32,
-        gateway: Arc<gateway::Gateway>
+        store: Arc<dyn ArmoryStore>
     ) -> Result<(Vec<Sword>, bool), Box<dyn Error + Send + Sync>> {
-        let params = HashMap::from([
-            ("page", page.to_string()),
-            ("per_page", 1000.to_string())
-        ]);
-        gateway.get("/armory", params).await
-            .map(|json| {
-                if !json.is_object() ||
-                    !json.has_key("data") ||
-                    !json["data"].is_array() ||
-                    !json.has_key("meta") ||
-                    !json["meta"].is_object() ||
-                    !json["meta"].has_key("has_next") ||
-                    !json["meta"]["has_next"].is_boolean()
-                {
-                    return Err(format!("Result is not valid: {}", json).into())
-                }
-                let swords = json["data"].members().map(|s| Sword::deserialize(s)).collect::<Result<Vec<Sword>, _>>()?;
-                Ok((swords, json["meta"]["has_next"].as_bool().unwrap()))
-            }
-        ).map_err(|e| e.to_string())?
+        let (swords, has_next) = store.page(page, 1000).await?;
+        let swords = swords.iter().map(Sword::deserialize).collect::<Result<Vec<Sword>, _>>()?;
+        Ok((swords, has_next))
     }
 
     pub async fn check(&self, owner: &String, id: Option<i64>) -> (usize, Option<Sword>) {
@@ -158,7 +354,7 @@ impl Swords {
         } else {
             self.cache.read().await
                 .iter()
-                .filter(|s| s.owner == *owner)
+                .filter(|s| s.owner.eq_ignore_ascii_case(owner))
                 .inspect(|_| count += 1)
                 .collect::<Vec<&Sword>>()
                 .choose(&mut rand::rng())
@@ -168,6 +364,124 @@ impl Swords {
         (count, sword)
     }
 
+    /// A sword of `owner` to fight with: the one with `id` if they own it, a random one otherwise
+    pub async fn blade(&self, owner: &Chatter, id: Option<i64>) -> Option<Sword> {
+        let cache = self.cache.read().await;
+        let mut owned = cache.iter().filter(|s| owner.owns(&s.owner));
+        match id {
+            Some(id) => owned.find(|s| s.id == Some(id)).cloned(),
+            None => owned.collect::<Vec<&Sword>>().choose(&mut rand::rng()).map(|s| (*s).clone())
+        }
+    }
+
+    pub async fn search(&self, query: &Query) -> Matches {
+        let cache = self.cache.read().await;
+        let found = cache.iter().filter(|s| query.matches(s)).collect::<Vec<&Sword>>();
+        Matches {
+            count: found.len(),
+            owners: found.iter().map(|s| s.owner.to_lowercase()).collect::<HashSet<_>>().len(),
+            sample: found.choose(&mut rand::rng()).map(|s| (*s).clone())
+        }
+    }
+
+    /// Counts of everything `owner` holds, None if they hold nothing
+    pub async fn collection(&self, owner: &str) -> Option<Collection> {
+        self.cache.read().await.index.owners.get(&owner.to_lowercase()).cloned()
+    }
+
+    pub async fn leaderboard(&self, n: usize) -> Leaderboard {
+        let cache = self.cache.read().await;
+        let owners = cache.index.owners.values();
+        Leaderboard {
+            most_blades: top(owners.clone().map(|c| (c.owner.as_str(), c.total)), n, false),
+            most_artifacts: top(owners.map(|c| (c.owner.as_str(), c.artifacts)), n, false),
+            rarest_materials: top(cache.index.all.materials.iter().map(|(m, c)| (m.as_str(), *c)), n, true)
+        }
+    }
+
+    pub async fn global_stats(&self) -> GlobalStats {
+        let cache = self.cache.read().await;
+        let index = &cache.index;
+        let combinations = || index.combinations.iter().map(|(k, c)| (k.as_str(), *c));
+        GlobalStats {
+            total: index.all.total,
+            owners: index.owners.len(),
+            artifacts: index.all.artifacts,
+            rarest_combination: top(combinations(), 1, true).pop(),
+            commonest_combination: top(combinations(), 1, false).pop()
+        }
+    }
+
+    /// Hand sword `id` from `from` to `to`. The cache changes right away and the store is updated
+    /// in the background, like a freshly drawn sword; if the store refuses, the cache is put back
+    pub async fn transfer(&self, store: Arc<dyn ArmoryStore>, id: i64, from: &Chatter, to: &Chatter) -> Result<Sword, String> {
+        let mut cache = self.cache.write().await;
+        let sword = Self::owned(&cache, id, from)?;
+        let moved = sword.handed_to(to);
+        cache.update(moved.clone());
+        self.spawn_updates(store, vec![(sword, moved.clone())]);
+        Ok(moved)
+    }
+
+    /// Swap two swords between their owners, both change hands or neither does
+    pub async fn exchange(&self, store: Arc<dyn ArmoryStore>, first: (i64, &Chatter), second: (i64, &Chatter)) -> Result<(), String> {
+        let mut cache = self.cache.write().await;
+        let (first_sword, second_sword) = (Self::owned(&cache, first.0, first.1)?, Self::owned(&cache, second.0, second.1)?);
+        let changes = vec![
+            (first_sword.clone(), first_sword.handed_to(second.1)),
+            (second_sword.clone(), second_sword.handed_to(first.1))
+        ];
+        for (_, moved) in &changes {
+            cache.update(moved.clone());
+        }
+        self.spawn_updates(store, changes);
+        Ok(())
+    }
+
+    fn owned(cache: &Cache, id: i64, owner: &Chatter) -> Result<Sword, String> {
+        cache.iter()
+            .find(|s| s.id == Some(id) && owner.owns(&s.owner))
+            .cloned()
+            .ok_or(format!("#{} does not belong to {}", id, owner.name))
+    }
+
+    /// Store every (before, after) change in order. When one fails the ones already stored are undone,
+    /// and cached swords that haven't moved on since go back to how they were
+    fn spawn_updates(&self, store: Arc<dyn ArmoryStore>, changes: Vec<(Sword, Sword)>) {
+        let cache = Arc::clone(&self.cache);
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        while pending.try_join_next().is_some() {}
+        pending.spawn(async move {
+            for (done, (_, after)) in changes.iter().enumerate() {
+                let Err(e) = Self::store_update(store.as_ref(), after).await else {
+                    continue;
+                };
+                log::error!("Failed to hand {} to {}: {}", after, after.owner, e);
+                for (before, _) in &changes[..done] {
+                    if let Err(e) = Self::store_update(store.as_ref(), before).await {
+                        log::error!("Failed to give {} back to {}: {}", before, before.owner, e);
+                    }
+                }
+                let mut cache = cache.write().await;
+                for (before, after) in &changes {
+                    let unchanged = cache.iter()
+                        .any(|s| s.id == after.id && s.owner == after.owner && s.history.len() == after.history.len());
+                    if unchanged {
+                        cache.update(before.clone());
+                    }
+                }
+                return;
+            }
+        });
+    }
+
+    async fn store_update(store: &dyn ArmoryStore, sword: &Sword) -> Result<(), String> {
+        let id = sword.id.ok_or("The sword has no id yet")?;
+        let changes = object!(owner: sword.owner.clone(), history: sword.history_json());
+        store.update(id, changes).await.map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
     pub async fn draw(&self, owner: &String, needle: bool) -> Result<Sword, Box<dyn Error + Send + Sync>> {
         let mut sword = self.roll_sword(owner, false, needle);
         if let Quality::Artifact = sword.quality {
@@ -221,6 +535,269 @@ impl Swords {
     }
 }
 
+/// One owner's swords, or everyone's, counted by kind
+#[derive(Debug, Clone, Default)]
+pub struct Collection {
+    /// Display name as last seen on a sword
+    pub owner: String,
+    pub total: usize,
+    pub artifacts: usize,
+    pub needles: usize,
+    /// Count per quality, from common to artifact
+    pub qualities: [usize; QUALITIES],
+    pub materials: HashMap<String, usize>,
+    pub types: HashMap<String, usize>
+}
+
+impl Collection {
+    fn add(&mut self, sword: &Sword) {
+        self.owner = sword.owner.clone();
+        self.total += 1;
+        self.qualities[sword.quality.rank()] += 1;
+        if sword.quality == Quality::Artifact {
+            self.artifacts += 1;
+        }
+        if sword.sword_type == SwordType::Needle {
+            self.needles += 1;
+        }
+        *self.materials.entry(sword.material.to_string()).or_default() += 1;
+        *self.types.entry(sword.sword_type.to_string()).or_default() += 1;
+    }
+
+    fn remove(&mut self, sword: &Sword) {
+        self.total -= 1;
+        self.qualities[sword.quality.rank()] -= 1;
+        if sword.quality == Quality::Artifact {
+            self.artifacts -= 1;
+        }
+        if sword.sword_type == SwordType::Needle {
+            self.needles -= 1;
+        }
+        decrement(&mut self.materials, &sword.material.to_string());
+        decrement(&mut self.types, &sword.sword_type.to_string());
+    }
+
+    /// Name and count of each quality present, from common up
+    pub fn quality_counts(&self) -> Vec<(&'static str, usize)> {
+        QUALITY_LABELS.iter().copied()
+            .zip(self.qualities.iter().copied())
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+}
+
+/// What `!armory` was asked to look for, every field that is set has to match
+#[derive(Debug, Default)]
+pub struct Query {
+    owner: Option<String>,
+    material: Option<Material>,
+    handle: Option<Material>,
+    sword_type: Option<SwordType>,
+    quality: Option<Quality>,
+    /// Lowercase part of the name or real name
+    name: Option<String>
+}
+
+impl Query {
+    /// Words like `@someone`, `adamantine`, `ruby handle`, `katana`, `masterwork` or `☼`,
+    /// anything else is looked for in artifact names
+    pub fn parse(args: &[&str]) -> Self {
+        let mut query = Query::default();
+        let mut name = Vec::new();
+        let words = args.iter().map(|a| a.to_lowercase()).collect::<Vec<_>>();
+        let mut i = 0;
+        while i < words.len() {
+            let word = words[i].as_str();
+            if let Some(owner) = word.strip_prefix('@') {
+                query.owner = Some(owner.to_owned());
+                i += 1;
+                continue;
+            }
+            // "rose gold" and "fine porcelain" take two words, the latter shadows the "fine" quality
+            let pair = words.get(i + 1).map(|next| format!("{} {}", word, next));
+            let material = match pair.as_deref().and_then(|p| Material::parse(Some(p)).ok().flatten()) {
+                Some(material) => Some((material, 2)),
+                None => Material::parse(Some(word)).ok().flatten().map(|m| (m, 1))
+            };
+            if let Some((material, used)) = material {
+                if words.get(i + used).is_some_and(|w| w == "handle") {
+                    query.handle = Some(material);
+                    i += used + 1;
+                } else {
+                    query.material = Some(material);
+                    i += used;
+                }
+                continue;
+            }
+            if let Ok(sword_type) = SwordType::parse(Some(word)) {
+                query.sword_type = Some(sword_type);
+            } else if let Some(quality) = Quality::parse(Some(word)).ok().or_else(|| Quality::from_label(word)) {
+                query.quality = Some(quality);
+            } else {
+                name.push(word);
+            }
+            i += 1;
+        }
+        if !name.is_empty() {
+            query.name = Some(name.join(" "));
+        }
+        query
+    }
+
+    fn matches(&self, sword: &Sword) -> bool {
+        let name = |n: &Option<String>| match (n, &self.name) {
+            (Some(name), Some(part)) => name.to_lowercase().contains(part.as_str()),
+            _ => false
+        };
+        unset_or(&self.owner, |o| sword.owner.eq_ignore_ascii_case(o))
+            && unset_or(&self.material, |m| sword.material == *m)
+            && unset_or(&self.handle, |h| sword.handle.as_ref() == Some(h))
+            && unset_or(&self.sword_type, |t| sword.sword_type == *t)
+            && unset_or(&self.quality, |q| sword.quality == *q)
+            && (self.name.is_none() || name(&sword.name) || name(&sword.real_name))
+    }
+}
+
+fn unset_or<T>(value: &Option<T>, check: impl Fn(&T) -> bool) -> bool {
+    match value {
+        Some(value) => check(value),
+        None => true
+    }
+}
+
+/// What a query found
+#[derive(Debug, Clone, Default)]
+pub struct Matches {
+    pub count: usize,
+    pub owners: usize,
+    /// One of the matching swords at random
+    pub sample: Option<Sword>
+}
+
+/// Who leads the armory
+#[derive(Debug, Clone, Default)]
+pub struct Leaderboard {
+    pub most_blades: Vec<(String, usize)>,
+    pub most_artifacts: Vec<(String, usize)>,
+    /// Materials with the fewest swords made of them
+    pub rarest_materials: Vec<(String, usize)>
+}
+
+/// The armory as a whole
+#[derive(Debug, Clone, Default)]
+pub struct GlobalStats {
+    pub total: usize,
+    pub owners: usize,
+    pub artifacts: usize,
+    /// Material and sword type seen the fewest times, with the count
+    pub rarest_combination: Option<(String, usize)>,
+    pub commonest_combination: Option<(String, usize)>
+}
+
+/// Counts kept in step with the cache, so statistics never walk every sword
+#[derive(Default)]
+struct Index {
+    /// By lowercase owner name
+    owners: HashMap<String, Collection>,
+    all: Collection,
+    /// By "material sword_type"
+    combinations: HashMap<String, usize>
+}
+
+impl Index {
+    fn add(&mut self, sword: &Sword) {
+        self.owners.entry(sword.owner.to_lowercase()).or_default().add(sword);
+        self.all.add(sword);
+        *self.combinations.entry(combination(sword)).or_default() += 1;
+    }
+
+    fn remove(&mut self, sword: &Sword) {
+        let key = sword.owner.to_lowercase();
+        if let Some(collection) = self.owners.get_mut(&key) {
+            collection.remove(sword);
+            if collection.total == 0 {
+                self.owners.remove(&key);
+            }
+        }
+        self.all.remove(sword);
+        decrement(&mut self.combinations, &combination(sword));
+    }
+}
+
+/// Cached swords with their index, every change that touches an owner or a kind goes through here
+struct Cache {
+    swords: Vec<Sword>,
+    index: Index
+}
+
+impl Cache {
+    fn new(swords: Vec<Sword>) -> Self {
+        let mut cache = Self { swords: Vec::new(), index: Index::default() };
+        cache.replace(swords);
+        cache
+    }
+
+    fn iter(&self) -> std::slice::Iter<'_, Sword> {
+        self.swords.iter()
+    }
+
+    /// Only for changes the index doesn't care about, like ids
+    fn iter_mut(&mut self) -> std::slice::IterMut<'_, Sword> {
+        self.swords.iter_mut()
+    }
+
+    fn push(&mut self, sword: Sword) {
+        self.index.add(&sword);
+        self.swords.push(sword);
+    }
+
+    /// Take every sword out, leaving the cache empty
+    fn take(&mut self) -> Vec<Sword> {
+        self.index = Index::default();
+        std::mem::take(&mut self.swords)
+    }
+
+    fn replace(&mut self, swords: Vec<Sword>) {
+        self.index = Index::default();
+        for sword in &swords {
+            self.index.add(sword);
+        }
+        self.swords = swords;
+    }
+
+    /// Swap in a newer copy of the sword with the same id
+    fn update(&mut self, sword: Sword) {
+        match self.swords.iter_mut().find(|s| s.id.is_some() && s.id == sword.id) {
+            Some(cached) => {
+                self.index.remove(cached);
+                self.index.add(&sword);
+                *cached = sword;
+            },
+            None => self.push(sword)
+        }
+    }
+}
+
+fn combination(sword: &Sword) -> String {
+    format!("{} {}", sword.material, sword.sword_type)
+}
+
+fn decrement(counts: &mut HashMap<String, usize>, key: &str) {
+    if let Some(count) = counts.get_mut(key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(key);
+        }
+    }
+}
+
+/// Highest counts first, or lowest ones for `rarest`. Ties are broken by name so the order is stable
+fn top<'a>(counts: impl Iterator<Item = (&'a str, usize)>, n: usize, rarest: bool) -> Vec<(String, usize)> {
+    let mut counts = counts.filter(|(_, count)| *count > 0).collect::<Vec<_>>();
+    counts.sort_by(|a, b| (if rarest { a.1.cmp(&b.1) } else { b.1.cmp(&a.1) }).then(a.0.cmp(b.0)));
+    counts.into_iter().take(n).map(|(name, count)| (name.to_owned(), count)).collect()
+}
+
 #[derive(Debug, PartialEq, Clone)]
 enum Material {
     Rosewood,
@@ -282,8 +859,30 @@ impl Material {
             _ => Err(format!("Unknown material: {}", string).into()),
         }
     }
+
+    /// Roughly the Mohs scale, with some liberties taken for the fantastic ones
+    fn hardness(&self) -> f64 {
+        match self {
+            Material::Plastic | Material::Lead | Material::Tin => 1.5,
+            Material::Wood => 2.0,
+            Material::Gold | Material::Zinc => 2.5,
+            Material::Silver | Material::Electrum => 2.7,
+            Material::Rosewood | Material::RoseGold | Material::Copper => 3.0,
+            Material::Bronze | Material::Brass => 3.5,
+            Material::Iron => 4.5,
+            Material::Steel | Material::Glass => 5.5,
+            Material::Porcelain => 6.5,
+            Material::Mithril | Material::Emerald => 7.5,
+            Material::Ruby | Material::Sapphire => 9.0,
+            Material::Diamond => 10.0,
+            Material::Adamantine => 11.0,
+        }
+    }
 }
 
+const QUALITIES: usize = 7;
+const QUALITY_LABELS: [&str; QUALITIES] = ["common", "well-crafted", "fine", "superior", "exceptional", "masterwork", "artifact"];
+
 #[derive(Debug, PartialEq, Clone)]
 enum Quality {
     Common,
@@ -323,6 +922,44 @@ impl Quality {
             _ => Err(format!("Unknown quality mark: {}", string).into())
         }
     }
+
+    /// Quality by its name, the way `!armory` queries spell it
+    fn from_label(word: &str) -> Option<Self> {
+        match word {
+            "common" => Some(Quality::Common),
+            "well-crafted" | "wellcrafted" => Some(Quality::WellCrafted),
+            "fine" => Some(Quality::Fine),
+            "superior" => Some(Quality::Superior),
+            "exceptional" => Some(Quality::Exceptional),
+            "masterwork" | "masterful" => Some(Quality::Masterful),
+            "artifact" => Some(Quality::Artifact),
+            _ => None
+        }
+    }
+
+    fn rank(&self) -> usize {
+        match self {
+            Quality::Common => 0,
+            Quality::WellCrafted => 1,
+            Quality::Fine => 2,
+            Quality::Superior => 3,
+            Quality::Exceptional => 4,
+            Quality::Masterful => 5,
+            Quality::Artifact => 6,
+        }
+    }
+
+    fn weight(&self) -> f64 {
+        match self {
+            Quality::Common => 1.0,
+            Quality::WellCrafted => 1.2,
+            Quality::Fine => 1.4,
+            Quality::Superior => 1.7,
+            Quality::Exceptional => 2.0,
+            Quality::Masterful => 2.5,
+            Quality::Artifact => 3.5,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -359,6 +996,19 @@ impl SwordType {
             _ => Err(format!("Unknown sword type: {}", string).into()),
         }
     }
+
+    /// Reach and heft, a zweihander keeps a needle at bay
+    fn weight(&self) -> f64 {
+        match self {
+            SwordType::Zweihander => 1.3,
+            SwordType::LongSword | SwordType::Katana => 1.2,
+            SwordType::Cutlass | SwordType::Scimitar => 1.1,
+            SwordType::ShortSword | SwordType::Rapier => 1.0,
+            SwordType::Dagger => 0.8,
+            SwordType::Tooth => 0.7,
+            SwordType::Needle => 0.5,
+        }
+    }
 }
 
 impl Distribution<SwordType> for StandardUniform {
@@ -484,7 +1134,18 @@ pub struct Sword {
     quality: Quality,
     name: Option<String>,
     real_name: Option<String>,
-    pub owner: String
+    pub owner: String,
+    /// Everyone who held the sword before the current owner, the first one it was forged for
+    history: Vec<Holder>,
+    /// Outbox key the sword was posted under, the store keeps it to recognize the same post made twice
+    client_id: Option<String>
+}
+
+#[derive(Debug, Clone)]
+struct Holder {
+    owner: String,
+    /// When the sword left them
+    until: String
 }
 
 impl Sword {
@@ -504,8 +1165,28 @@ impl Sword {
         self.id = Some(id);
     }
 
+    /// Short name for narration, e.g. "steel katana" or an artifact's name
+    pub fn title(&self) -> String {
+        match (&self.quality, &self.name) {
+            (Quality::Artifact, Some(name)) => format!("\"{}\"", name),
+            _ => format!("{} {}", self.material, self.sword_type)
+        }
+    }
+
+    /// How well the sword fights, a sturdier handle helps a little
+    pub fn power(&self) -> f64 {
+        let handle = self.handle.as_ref().map(|h| 1.0 + h.hardness() / 50.0).unwrap_or(1.0);
+        self.quality.weight() * (1.0 + self.material.hardness() / 5.0) * self.sword_type.weight() * handle
+    }
+
+    /// Chance of this sword winning a duel against the other
+    pub fn odds_against(&self, other: &Sword) -> f64 {
+        let (mine, theirs) = (self.power(), other.power());
+        mine / (mine + theirs)
+    }
+
     pub fn serialize(&self) -> json::JsonValue {
-        object!(
+        let mut json = object!(
             material: self.material.to_string(),
             handle: self.handle.clone().map(|m| m.to_string()),
             sword_type: self.sword_type.to_string(),
@@ -513,12 +1194,46 @@ impl Sword {
             name: self.name.clone(),
             real_name: self.real_name.clone(),
             owner: self.owner.clone()
-        )
+        );
+        if !self.history.is_empty() {
+            json["history"] = self.history_json();
+        }
+        json
+    }
+
+    /// The sword as it is once `to` holds it, the current owner joins its history
+    fn handed_to(&self, to: &Chatter) -> Sword {
+        let mut moved = self.clone();
+        moved.history.push(Holder { owner: self.owner.clone(), until: chrono::Utc::now().to_rfc3339() });
+        moved.owner = to.name.clone();
+        moved
+    }
+
+    fn history_json(&self) -> json::JsonValue {
+        self.history.iter()
+            .map(|h| object!(owner: h.owner.clone(), until: h.until.clone()))
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    /// Who the sword was forged for and who held it since, None if it never changed hands
+    pub fn provenance(&self) -> Option<String> {
+        let (forged, held) = self.history.split_first()?;
+        let mut provenance = format!("Forged for {}", forged.owner);
+        if !held.is_empty() {
+            let held = held.iter().map(|h| h.owner.as_str()).collect::<Vec<_>>();
+            provenance += format!(", later held by {}", held.join(", ")).as_str();
+        }
+        Some(provenance)
     }
 
     pub fn deserialize(json: &json::JsonValue) -> Result<Sword, Box<dyn Error + Send + Sync>> {
+        Self::from_json(json, Some(json["id"].as_i64().ok_or("No identifier")?))
+    }
+
+    fn from_json(json: &json::JsonValue, id: Option<i64>) -> Result<Sword, Box<dyn Error + Send + Sync>> {
         Ok(Sword {
-            id: Some(json["id"].as_i64().ok_or("No identifier")?),
+            id,
             material: Material::parse(json["material"].as_str())?.ok_or("Main material cannot be none")?,
             handle: Material::parse(json["handle"].as_str())?,
             sword_type: SwordType::parse(json["sword_type"].as_str())?,
@@ -526,6 +1241,15 @@ impl Sword {
             name: json["name"].as_str().map(str::to_owned),
             real_name:json["real_name"].as_str().map(str::to_owned),
             owner: json["owner"].as_str().map(str::to_owned).ok_or("Owner name is missing")?,
+            history: json["history"].members()
+                .map(|h| -> Result<Holder, Box<dyn Error + Send + Sync>> {
+                    Ok(Holder {
+                        owner: h["owner"].as_str().ok_or("Previous owner name is missing")?.to_owned(),
+                        until: h["until"].as_str().unwrap_or_default().to_owned()
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            client_id: json["client_id"].as_str().map(str::to_owned)
         })
     }
 
@@ -578,3 +1302,220 @@ fn is_vowel(character: Option<char>) -> bool {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use async_trait::async_trait;
+    use crate::gateway::Gateway;
+    use crate::store::MemoryStore;
+    use crate::test_support::{self, FakeGateway};
+
+    /// Keeps swords in memory but posts go wrong: with `lost` the first one is saved and the answer never arrives,
+    /// like a gateway timing out after the write, with `refused` every one is turned away
+    struct FlakyStore {
+        swords: MemoryStore,
+        lost: AtomicBool,
+        refused: bool
+    }
+
+    impl FlakyStore {
+        fn new(swords: Vec<json::JsonValue>, lost: bool, refused: bool) -> Self {
+            Self { swords: MemoryStore::new(swords), lost: AtomicBool::new(lost), refused }
+        }
+    }
+
+    #[async_trait]
+    impl ArmoryStore for FlakyStore {
+        async fn page(&self, page: u32, per_page: u32) -> Result<(Vec<json::JsonValue>, bool), Box<dyn Error + Send + Sync>> {
+            self.swords.page(page, per_page).await
+        }
+
+        async fn create(&self, sword: json::JsonValue) -> Result<i64, Box<dyn Error + Send + Sync>> {
+            if self.refused {
+                return Err(GatewayError::Status { status: 503, body: String::new(), retry_after: None }.into());
+            }
+            let id = self.swords.create(sword).await?;
+            if self.lost.swap(false, Ordering::SeqCst) {
+                return Err(GatewayError::Timeout.into());
+            }
+            Ok(id)
+        }
+
+        async fn update(&self, id: i64, changes: json::JsonValue) -> Result<json::JsonValue, Box<dyn Error + Send + Sync>> {
+            self.swords.update(id, changes).await
+        }
+    }
+
+    #[tokio::test]
+    async fn loads_every_page_and_follows_remote_edits() {
+        let gateway = FakeGateway::start(vec![
+            test_support::sword(1, "first"),
+            test_support::sword(2, "first"),
+            test_support::sword(3, "second")
+        ]).await;
+        let dir = test_support::temp_dir("armory-sync");
+        let client: Arc<dyn ArmoryStore> = Arc::new(Gateway::init(gateway.url(), "secret".to_owned()).unwrap());
+        let swords = Swords::new(dir.join("elven.txt"), dir.join("outbox.jsonl"), Arc::clone(&client)).await.unwrap();
+        assert!(swords.sync_status().synced);
+        assert_eq!(swords.sync_status().remote, 3);
+        assert_eq!(swords.check(&"first".to_owned(), None).await.0, 2);
+
+        gateway.set_swords(vec![test_support::sword(1, "first"), test_support::sword(3, "third")]).await;
+        let status = swords.sync(Arc::clone(&client)).await.unwrap();
+        assert_eq!(status.remote, 2);
+        assert_eq!(status.unsynced, 0);
+        assert_eq!(swords.check(&"first".to_owned(), None).await.0, 1);
+        assert_eq!(swords.check(&"third".to_owned(), None).await.0, 1);
+    }
+
+    #[tokio::test]
+    async fn saves_logged_swords_to_the_store() {
+        let store = Arc::new(MemoryStore::new(vec![test_support::sword(1, "first")]));
+        let dir = test_support::temp_dir("armory-store");
+        let providers = test_support::providers_with(store.clone(), &dir).await;
+        let sword = Sword::from_json(&test_support::sword(0, "second"), None).unwrap();
+        providers.swords.log(sword, Arc::clone(&providers.armory)).await;
+        providers.swords.flush(test_support::TIMEOUT).await;
+
+        let stored = store.swords().await;
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[1]["id"], 2);
+        assert_eq!(stored[1]["owner"], "second");
+        let (_, cached) = providers.swords.check(&"second".to_owned(), None).await;
+        assert_eq!(cached.and_then(|s| s.id), Some(2));
+    }
+
+    #[tokio::test]
+    async fn posts_a_sword_once_when_the_answer_is_lost() {
+        let store = Arc::new(FlakyStore::new(Vec::new(), true, false));
+        let dir = test_support::temp_dir("armory-lost");
+        let swords = Swords::new(dir.join("elven.txt"), dir.join("outbox.jsonl"), store.clone()).await.unwrap();
+        swords.log(Sword::from_json(&test_support::sword(0, "first"), None).unwrap(), store.clone()).await;
+        swords.flush(test_support::TIMEOUT).await;
+        // The post may have been stored, so it isn't repeated until a sync has looked
+        Swords::repost(&swords.outbox, &swords.cache, store.as_ref()).await;
+        assert_eq!(store.swords.swords().await.len(), 1);
+        assert_eq!(swords.outbox.pending().len(), 1);
+
+        swords.sync(store.clone()).await.unwrap();
+        assert_eq!(store.swords.swords().await.len(), 1);
+        assert!(swords.outbox.pending().is_empty());
+        let (_, cached) = swords.check(&"first".to_owned(), None).await;
+        assert_eq!(cached.and_then(|s| s.id), Some(1));
+    }
+
+    #[tokio::test]
+    async fn adopts_only_remote_swords_posted_under_our_key() {
+        let store = Arc::new(FlakyStore::new(vec![test_support::sword(1, "first")], false, true));
+        let dir = test_support::temp_dir("armory-adopt");
+        let swords = Swords::new(dir.join("elven.txt"), dir.join("outbox.jsonl"), store.clone()).await.unwrap();
+        swords.log(Sword::from_json(&test_support::sword(0, "first"), None).unwrap(), store.clone()).await;
+        swords.flush(test_support::TIMEOUT).await;
+
+        // Another tool stored the very same sword, it isn't ours
+        swords.sync(store.clone()).await.unwrap();
+        assert_eq!(swords.sync_status().unsynced, 1);
+        let key = swords.outbox.pending()[0].key.clone();
+
+        let mut ours = test_support::sword(0, "first");
+        ours["client_id"] = key.as_str().into();
+        store.swords.create(ours).await.unwrap();
+        // Still being posted, whoever holds the entry finishes it
+        assert!(swords.outbox.try_claim(&key));
+        assert_eq!(swords.sync(store.clone()).await.unwrap().unsynced, 0);
+        assert_eq!(swords.outbox.pending().len(), 1);
+
+        swords.outbox.release(&key);
+        swords.sync(store.clone()).await.unwrap();
+        assert!(swords.outbox.pending().is_empty());
+        assert_eq!(swords.check(&"first".to_owned(), None).await.0, 2);
+    }
+
+    #[test]
+    fn better_swords_win_more_duels() {
+        let mut strong = test_support::sword(1, "first");
+        strong["material"] = "adamantine".into();
+        strong["quality"] = "☼".into();
+        strong["sword_type"] = "zweihander".into();
+        let mut weak = test_support::sword(2, "second");
+        weak["material"] = "plastic".into();
+        weak["quality"] = " ".into();
+        weak["sword_type"] = "needle".into();
+        let (strong, weak) = (Sword::deserialize(&strong).unwrap(), Sword::deserialize(&weak).unwrap());
+        let even = Sword::deserialize(&test_support::sword(3, "third")).unwrap();
+
+        assert!(strong.odds_against(&weak) > 0.9);
+        assert!((strong.odds_against(&weak) + weak.odds_against(&strong) - 1.0).abs() < 1e-9);
+        assert!((even.odds_against(&even) - 0.5).abs() < 1e-9);
+        assert_eq!(weak.title(), "plastic needle");
+    }
+
+    #[tokio::test]
+    async fn keeps_statistics_in_step_with_the_cache() {
+        let mut needle = test_support::sword(3, "second");
+        needle["material"] = "plastic".into();
+        needle["sword_type"] = "needle".into();
+        let store = Arc::new(MemoryStore::new(vec![
+            test_support::sword(1, "first"),
+            test_support::sword(2, "first"),
+            needle
+        ]));
+        let dir = test_support::temp_dir("armory-stats");
+        let providers = test_support::providers_with(store.clone(), &dir).await;
+        let swords = &providers.swords;
+
+        let first = swords.collection("First").await.unwrap();
+        assert_eq!((first.total, first.needles, first.quality_counts()), (2, 0, vec![("fine", 2)]));
+        assert_eq!(swords.collection("second").await.unwrap().needles, 1);
+        let top = swords.leaderboard(3).await;
+        assert_eq!(top.most_blades, vec![("first".to_owned(), 2), ("second".to_owned(), 1)]);
+        assert!(top.most_artifacts.is_empty());
+        assert_eq!(top.rarest_materials, vec![("plastic".to_owned(), 1), ("steel".to_owned(), 2)]);
+
+        swords.transfer(Arc::clone(&providers.armory), 1, &Chatter::named("first"), &Chatter::named("second")).await.unwrap();
+        assert_eq!(swords.collection("first").await.unwrap().total, 1);
+        assert_eq!(swords.collection("second").await.unwrap().total, 2);
+        swords.transfer(Arc::clone(&providers.armory), 2, &Chatter::named("first"), &Chatter::named("second")).await.unwrap();
+        assert!(swords.collection("first").await.is_none());
+
+        swords.flush(test_support::TIMEOUT).await;
+        swords.sync(Arc::clone(&providers.armory)).await.unwrap();
+        let global = swords.global_stats().await;
+        assert_eq!((global.total, global.owners, global.artifacts), (3, 1, 0));
+        assert_eq!(global.rarest_combination, Some(("plastic needle".to_owned(), 1)));
+        assert_eq!(global.commonest_combination, Some(("steel katana".to_owned(), 2)));
+    }
+
+    #[tokio::test]
+    async fn searches_by_owner_kind_and_name() {
+        let mut artifact = test_support::sword(2, "Second");
+        artifact["material"] = "adamantine".into();
+        artifact["handle"] = "rose gold".into();
+        artifact["quality"] = "?".into();
+        artifact["name"] = "Glimmerfang".into();
+        artifact["real_name"] = "Ithil Rauko".into();
+        let mut porcelain = test_support::sword(3, "first");
+        porcelain["material"] = "fine porcelain".into();
+        let store = Arc::new(MemoryStore::new(vec![test_support::sword(1, "first"), artifact, porcelain]));
+        let dir = test_support::temp_dir("armory-search");
+        let providers = test_support::providers_with(store, &dir).await;
+        let search = |args: &'static [&'static str]| {
+            let swords = Arc::clone(&providers.swords);
+            async move { swords.search(&Query::parse(args)).await }
+        };
+
+        let katanas = search(&["katana"]).await;
+        assert_eq!((katanas.count, katanas.owners), (3, 2));
+        let found = search(&["@second", "Artifact"]).await;
+        assert_eq!((found.count, found.sample.and_then(|s| s.id)), (1, Some(2)));
+        assert_eq!(search(&["fine", "porcelain"]).await.sample.and_then(|s| s.id), Some(3));
+        assert_eq!(search(&["fine"]).await.count, 2);
+        assert_eq!(search(&["+", "@first"]).await.count, 2);
+        assert_eq!(search(&["rose", "gold", "handle"]).await.count, 1);
+        assert_eq!(search(&["glimmer"]).await.count, 1);
+        assert_eq!(search(&["ithil", "rauko"]).await.count, 1);
+        assert_eq!(search(&["adamantine", "masterwork"]).await.count, 0);
+    }
+}
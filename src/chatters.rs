@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::twitch::TwitchMessage;
+
+/// Someone in chat: the lowercase login commands name them by,
+/// and the name their swords are kept under, which is the display name they had when drawing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chatter {
+    pub login: String,
+    pub name: String
+}
+
+impl Chatter {
+    pub fn of(message: &TwitchMessage) -> Self {
+        Self {
+            login: message.login.to_lowercase(),
+            name: message.display_name.clone()
+        }
+    }
+
+    /// Someone whose display name is just their login
+    #[cfg(test)]
+    pub fn named(name: &str) -> Self {
+        Self { login: name.to_lowercase(), name: name.to_owned() }
+    }
+
+    /// Whether a sword with this owner belongs to them. Localized display names
+    /// don't resemble the login, so both are checked
+    pub fn owns(&self, owner: &str) -> bool {
+        owner.eq_ignore_ascii_case(&self.login) || owner.eq_ignore_ascii_case(&self.name)
+    }
+}
+
+/// Display names of everyone who spoke since startup, by lowercase login
+pub struct Chatters {
+    names: Mutex<HashMap<String, String>>
+}
+
+impl Chatters {
+    pub fn new() -> Self {
+        Self {
+            names: Mutex::new(HashMap::new())
+        }
+    }
+
+    pub fn seen(&self, message: &TwitchMessage) {
+        let mut names = self.names.lock().unwrap_or_else(|e| e.into_inner());
+        names.insert(message.login.to_lowercase(), message.display_name.clone());
+    }
+
+    /// Who `@login` refers to. Someone who hasn't spoken yet is only known through `owner`,
+    /// the name of a sword they already hold
+    pub fn resolve(&self, login: &str, owner: Option<String>) -> Option<Chatter> {
+        let login = login.trim_start_matches('@').to_lowercase();
+        let names = self.names.lock().unwrap_or_else(|e| e.into_inner());
+        let name = names.get(&login).cloned().or(owner)?;
+        Some(Chatter { login, name })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_logins_to_known_chatters() {
+        let chatters = Chatters::new();
+        assert_eq!(chatters.resolve("@somebody", None), None);
+        let known = chatters.resolve("@SomeBody", Some("SomeBody".to_owned())).unwrap();
+        assert_eq!(known.login, "somebody");
+
+        let localized = Chatter { login: "pingu".to_owned(), name: "ペンギン".to_owned() };
+        assert!(localized.owns("ペンギン") && localized.owns("Pingu"));
+        assert!(!localized.owns("someone"));
+    }
+}
@@ -0,0 +1,66 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+
+use crate::config::FeatureKey;
+use crate::irc::Context;
+use crate::permission::Permission;
+use crate::twitch::TwitchMessage;
+
+/// A chat command the bot reacts to
+#[async_trait]
+pub trait Command: Send + Sync {
+    /// Primary trigger, e.g. "!draw"
+    fn name(&self) -> &str;
+
+    /// Additional triggers handled by the same command
+    fn aliases(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Feature that has to be enabled in a channel for the command to run
+    fn feature(&self) -> FeatureKey;
+
+    /// Minimum permission level to run the command, unless overridden per channel
+    fn permission(&self) -> Permission {
+        Permission::Everyone
+    }
+
+    /// Whether a message triggers this command. By default the first word
+    /// has to match the name or an alias exactly, so "!np" does not fire on "!npc"
+    fn matches(&self, text: &str) -> bool {
+        text.split_whitespace()
+            .next()
+            .is_some_and(|token| token == self.name() || self.aliases().contains(&token))
+    }
+
+    /// Run the command. Returns true if the bot should exit
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, args: &[&str]) -> Result<bool, Box<dyn Error>>;
+}
+
+pub struct Registry {
+    commands: Vec<Box<dyn Command>>
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {
+            commands: Vec::new()
+        }
+    }
+
+    pub fn register(&mut self, command: impl Command + 'static) -> &mut Self {
+        if self.commands.iter().any(|c| c.matches(command.name())) {
+            log::warn!("Command {} is shadowed by an earlier registration", command.name());
+        }
+        self.commands.push(Box::new(command));
+        self
+    }
+
+    /// Find a command triggered by the message text, along with its arguments
+    pub fn find<'a>(&self, text: &'a str) -> Option<(&dyn Command, Vec<&'a str>)> {
+        self.commands.iter()
+            .find(|c| c.matches(text))
+            .map(|c| (c.as_ref(), text.split_whitespace().skip(1).collect()))
+    }
+}
@@ -1,8 +1,131 @@
+use std::fmt;
 use std::error::Error;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use std::path::PathBuf;
 
+use json::{object, JsonValue};
+
+use crate::permission::Permission;
+
+/// Owner when the config doesn't list any
+const DEFAULT_OWNER: &str = "nichePenguin";
+
+#[derive(Clone)]
 pub struct Config {
-    pub channels: Vec<ChannelConfig>
+    pub channels: Vec<ChannelConfig>,
+    pub cooldowns: HashMap<String, Cooldown>,
+    /// User ids or logins of the bot owners
+    pub owners: Vec<String>,
+    pub connection: Connection
+}
+
+/// IRC connection settings, Twitch defaults unless set in the config file
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub nickname: String,
+    pub server: String,
+    pub port: u16,
+    pub use_tls: bool,
+    pub queue_delay_ms: u64,
+    /// Replies still queued after this long are dropped, 0 keeps them forever
+    pub max_age_ms: u64,
+    /// Append an invisible character to a message identical to the previous one, so Twitch lets it through.
+    /// Replies to a different message than the previous one always get it
+    pub duplicate_suffix: bool,
+    /// PING the server after this long without traffic
+    pub ping_interval_secs: u64,
+    /// Reconnect when the PING goes unanswered for this long
+    pub ping_timeout_secs: u64,
+    pub capabilities: Vec<String>
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self {
+            nickname: "nichePenguin".to_owned(),
+            server: "irc.twitch.tv".to_owned(),
+            port: 6667,
+            use_tls: false,
+            queue_delay_ms: 850,
+            max_age_ms: 60_000,
+            duplicate_suffix: false,
+            ping_interval_secs: 60,
+            ping_timeout_secs: 10,
+            capabilities: vec![
+                "twitch.tv/membership".to_owned(),
+                "twitch.tv/tags".to_owned()
+            ]
+        }
+    }
+}
+
+impl Connection {
+    /// Settings overridden by NPBOT_NICKNAME, NPBOT_SERVER, NPBOT_PORT, NPBOT_TLS,
+    /// NPBOT_QUEUE_DELAY_MS and NPBOT_CAPABILITIES (comma separated) when set
+    pub fn with_env(self) -> Result<Self, Box<dyn Error>> {
+        self.with_vars(|name| std::env::var(name).ok())
+    }
+
+    fn with_vars(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self, Box<dyn Error>> {
+        if let Some(nickname) = var("NPBOT_NICKNAME") {
+            self.nickname = nickname;
+        }
+        if let Some(server) = var("NPBOT_SERVER") {
+            self.server = server;
+        }
+        if let Some(port) = var("NPBOT_PORT") {
+            self.port = port.parse().map_err(|e| format!("Invalid NPBOT_PORT: {}", e))?;
+        }
+        if let Some(use_tls) = var("NPBOT_TLS") {
+            self.use_tls = use_tls.parse().map_err(|e| format!("Invalid NPBOT_TLS: {}", e))?;
+        }
+        if let Some(delay) = var("NPBOT_QUEUE_DELAY_MS") {
+            self.queue_delay_ms = delay.parse().map_err(|e| format!("Invalid NPBOT_QUEUE_DELAY_MS: {}", e))?;
+        }
+        if let Some(capabilities) = var("NPBOT_CAPABILITIES") {
+            self.capabilities = capabilities.split(',')
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+                .map(str::to_owned)
+                .collect();
+        }
+        Ok(self)
+    }
+}
+
+impl Config {
+    /// Cooldown of a command in a channel, channel entries take precedence over global ones
+    pub fn cooldown(&self, command: &str, channel: &str) -> Option<&Cooldown> {
+        self.channels.iter()
+            .find(|c| c.name == channel)
+            .and_then(|c| c.cooldowns.get(command))
+            .or_else(|| self.cooldowns.get(command))
+    }
+
+    /// Channel override of the permission level for a command, looked up by command name first, then by feature
+    pub fn permission(&self, command: &str, feature: &FeatureKey, channel: &str) -> Option<Permission> {
+        let channel = self.channels.iter().find(|c| c.name == channel)?;
+        channel.permissions.get(command)
+            .or_else(|| channel.permissions.get(&feature.to_string()))
+            .copied()
+    }
+
+    /// Channel config by name, added as inactive with no features if missing
+    pub fn channel_mut(&mut self, name: &str) -> &mut ChannelConfig {
+        if let Some(index) = self.channels.iter().position(|c| c.name == name) {
+            return &mut self.channels[index];
+        }
+        self.channels.push(ChannelConfig {
+            active: false,
+            name: name.to_owned(),
+            features: Vec::new(),
+            cooldowns: HashMap::new(),
+            permissions: HashMap::new(),
+            events: HashMap::new()
+        });
+        self.channels.last_mut().expect("Channel was just added")
+    }
 }
 
 /// Calculate channels to disconnect or connect after a config update
@@ -35,14 +158,46 @@ fn channel<'a, 'b>(name: &'b String, config: &'a Config) -> &'a ChannelConfig {
     config.channels.iter().find(|c| c.name == *name).expect("Original set source always contains it")
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChannelConfig {
     pub active: bool,
     pub name: String,
-    pub features: Vec<FeatureKey>
+    pub features: Vec<FeatureKey>,
+    pub cooldowns: HashMap<String, Cooldown>,
+    pub permissions: HashMap<String, Permission>,
+    /// Response templates for USERNOTICE events, keyed by `msg-id`
+    pub events: HashMap<String, String>
 }
 
-#[derive(PartialEq, Eq, Debug)]
+impl ChannelConfig {
+    pub fn enable(&mut self, key: FeatureKey) {
+        let negated = FeatureKey::Not(Box::new(key.clone()));
+        self.features.retain(|f| *f != negated);
+        if !self.features.contains(&FeatureKey::Full) && !self.features.contains(&key) {
+            self.features.push(key);
+        }
+    }
+
+    pub fn disable(&mut self, key: FeatureKey) {
+        let negated = FeatureKey::Not(Box::new(key.clone()));
+        self.features.retain(|f| *f != key && *f != negated);
+        if self.features.contains(&FeatureKey::Full) {
+            self.features.push(negated);
+        }
+    }
+}
+
+/// Minimum time between uses of a command, per scope
+#[derive(Debug, Clone, Default)]
+pub struct Cooldown {
+    pub global: Option<Duration>,
+    pub channel: Option<Duration>,
+    pub user: Option<Duration>,
+    /// Answer the first call on cooldown with a notice instead of ignoring it
+    pub notify: bool
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub enum FeatureKey {
     Any,
     Full,
@@ -56,11 +211,36 @@ pub enum FeatureKey {
     Ping,
     Needle,
     Np,
+    Duel,
+    Trade,
     Not(Box<FeatureKey>),
     Unknown(String),
 }
 
-fn parse_feature(string: &str) -> FeatureKey {
+impl fmt::Display for FeatureKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeatureKey::Any => write!(f, "any"),
+            FeatureKey::Full => write!(f, "full"),
+            FeatureKey::Tarot => write!(f, "tarot"),
+            FeatureKey::Moon => write!(f, "moon"),
+            FeatureKey::Rice => write!(f, "rice"),
+            FeatureKey::Hmmm => write!(f, "hmm"),
+            FeatureKey::Mmmm => write!(f, "mmm"),
+            FeatureKey::BugAd => write!(f, "bug_ad"),
+            FeatureKey::Needle => write!(f, "needle"),
+            FeatureKey::Ping => write!(f, "ping"),
+            FeatureKey::Np => write!(f, "np"),
+            FeatureKey::Duel => write!(f, "duel"),
+            FeatureKey::Trade => write!(f, "trade"),
+            FeatureKey::VoidStranger => write!(f, "voidstranger"),
+            FeatureKey::Not(key) => write!(f, "!{}", key),
+            FeatureKey::Unknown(string) => write!(f, "{}", string),
+        }
+    }
+}
+
+pub fn parse_feature(string: &str) -> FeatureKey {
     if string.starts_with("!") {
         let parsed = parse_feature(&string[1..]);
         // Double negation supported :D
@@ -81,6 +261,8 @@ fn parse_feature(string: &str) -> FeatureKey {
         "needle" => FeatureKey::Needle,
         "ping" => FeatureKey::Ping,
         "np" => FeatureKey::Np,
+        "duel" => FeatureKey::Duel,
+        "trade" => FeatureKey::Trade,
         "voidstranger" => FeatureKey::VoidStranger,
         _ => {
             log::warn!("Parsing unknown feature: {}", string);
@@ -100,18 +282,167 @@ pub fn from_json_string(data: &str) -> Result<Config, Box<dyn Error>> {
             .push(parse_channel(channel)
                 .map_err(|e| format!{"Error parsing channel at {} : {}", index, e})?);
     }
-    Ok(Config { channels })
+    let cooldowns = parse_cooldowns(&raw_json["cooldowns"])?;
+    // Configs written before owners existed keep the owner the safe word used to be tied to
+    let owners = if raw_json.has_key("owners") {
+        raw_json["owners"].members()
+            .map(|o| o.as_str().map(str::to_owned).ok_or("Failed to parse \"owners\""))
+            .collect::<Result<Vec<String>, _>>()?
+    } else {
+        log::warn!("No \"owners\" in config, defaulting to {}", DEFAULT_OWNER);
+        vec![DEFAULT_OWNER.to_owned()]
+    };
+    if owners.is_empty() {
+        log::warn!("No owners configured, owner-only commands are disabled");
+    }
+    let connection = parse_connection(&raw_json["connection"])
+        .map_err(|e| format!("Error parsing connection: {}", e))?;
+    Ok(Config { channels, cooldowns, owners, connection })
 }
 
 pub fn from_json(path: &std::path::PathBuf) -> Result<Config, Box<dyn Error>> {
     from_json_string(std::fs::read_to_string(path)?.as_str())
 }
 
+/// Write the config to disk through a temporary file, so the file watcher never sees it half-written.
+/// Keys the bot doesn't know about, at the top level, in "connection" or in a channel, are kept from the file
+pub fn save(config: &Config, path: &PathBuf) -> Result<(), Box<dyn Error>> {
+    let mut json = to_json(config);
+    if let Some(existing) = std::fs::read_to_string(path).ok().and_then(|data| json::parse(&data).ok()) {
+        keep_unknown(&mut json, &existing, &["cooldowns"]);
+        keep_unknown(&mut json["connection"], &existing["connection"], &[]);
+        for channel in json["channels"].members_mut() {
+            if let Some(old) = existing["channels"].members().find(|c| c["name"] == channel["name"]) {
+                keep_unknown(channel, old, &["cooldowns", "permissions", "events"]);
+            }
+        }
+    }
+    let temp = path.with_extension("tmp");
+    std::fs::write(&temp, json::stringify_pretty(json, 4))?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// Copy over keys of `existing` that are neither written nor among the `optional` ones left out when empty
+fn keep_unknown(written: &mut JsonValue, existing: &JsonValue, optional: &[&str]) {
+    for (key, value) in existing.entries() {
+        if !written.has_key(key) && !optional.contains(&key) {
+            written[key] = value.clone();
+        }
+    }
+}
+
+fn to_json(config: &Config) -> JsonValue {
+    let connection = &config.connection;
+    let mut json = object!(
+        owners: config.owners.clone(),
+        connection: object!(
+            nickname: connection.nickname.clone(),
+            server: connection.server.clone(),
+            port: connection.port,
+            use_tls: connection.use_tls,
+            queue_delay_ms: connection.queue_delay_ms,
+            max_age_ms: connection.max_age_ms,
+            duplicate_suffix: connection.duplicate_suffix,
+            ping_interval_secs: connection.ping_interval_secs,
+            ping_timeout_secs: connection.ping_timeout_secs,
+            capabilities: connection.capabilities.clone()
+        ),
+        channels: config.channels.iter().map(channel_to_json).collect::<Vec<_>>()
+    );
+    if !config.cooldowns.is_empty() {
+        json["cooldowns"] = cooldowns_to_json(&config.cooldowns);
+    }
+    json
+}
+
+fn channel_to_json(channel: &ChannelConfig) -> JsonValue {
+    let mut json = object!(
+        active: channel.active,
+        name: channel.name.clone(),
+        features: channel.features.iter().map(|f| f.to_string()).collect::<Vec<_>>()
+    );
+    if !channel.cooldowns.is_empty() {
+        json["cooldowns"] = cooldowns_to_json(&channel.cooldowns);
+    }
+    if !channel.permissions.is_empty() {
+        let mut permissions = JsonValue::new_object();
+        for (key, level) in &channel.permissions {
+            permissions[key.as_str()] = level.to_string().into();
+        }
+        json["permissions"] = permissions;
+    }
+    if !channel.events.is_empty() {
+        let mut events = JsonValue::new_object();
+        for (event, template) in &channel.events {
+            events[event.as_str()] = template.clone().into();
+        }
+        json["events"] = events;
+    }
+    json
+}
+
+fn cooldowns_to_json(cooldowns: &HashMap<String, Cooldown>) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    for (command, cooldown) in cooldowns {
+        json[command.as_str()] = object!(
+            global: cooldown.global.map(|d| d.as_secs_f64()),
+            channel: cooldown.channel.map(|d| d.as_secs_f64()),
+            user: cooldown.user.map(|d| d.as_secs_f64()),
+            notify: cooldown.notify
+        );
+    }
+    json
+}
+
+fn parse_connection(json: &json::JsonValue) -> Result<Connection, Box<dyn Error>> {
+    let mut connection = Connection::default();
+    if json.is_null() {
+        return Ok(connection);
+    }
+    if !json["nickname"].is_null() {
+        connection.nickname = json["nickname"].as_str().ok_or("Failed to parse \"nickname\"")?.to_owned();
+    }
+    if !json["server"].is_null() {
+        connection.server = json["server"].as_str().ok_or("Failed to parse \"server\"")?.to_owned();
+    }
+    if !json["port"].is_null() {
+        connection.port = json["port"].as_u16().ok_or("Failed to parse \"port\"")?;
+    }
+    if !json["use_tls"].is_null() {
+        connection.use_tls = json["use_tls"].as_bool().ok_or("Failed to parse \"use_tls\"")?;
+    }
+    if !json["queue_delay_ms"].is_null() {
+        connection.queue_delay_ms = json["queue_delay_ms"].as_u64().ok_or("Failed to parse \"queue_delay_ms\"")?;
+    }
+    if !json["max_age_ms"].is_null() {
+        connection.max_age_ms = json["max_age_ms"].as_u64().ok_or("Failed to parse \"max_age_ms\"")?;
+    }
+    if !json["duplicate_suffix"].is_null() {
+        connection.duplicate_suffix = json["duplicate_suffix"].as_bool().ok_or("Failed to parse \"duplicate_suffix\"")?;
+    }
+    if !json["ping_interval_secs"].is_null() {
+        connection.ping_interval_secs = json["ping_interval_secs"].as_u64().ok_or("Failed to parse \"ping_interval_secs\"")?;
+    }
+    if !json["ping_timeout_secs"].is_null() {
+        connection.ping_timeout_secs = json["ping_timeout_secs"].as_u64().ok_or("Failed to parse \"ping_timeout_secs\"")?;
+    }
+    if !json["capabilities"].is_null() {
+        connection.capabilities = json["capabilities"].members()
+            .map(|c| c.as_str().map(str::to_owned).ok_or("Failed to parse \"capabilities\""))
+            .collect::<Result<Vec<String>, _>>()?;
+    }
+    Ok(connection)
+}
+
 fn parse_channel(json: &json::JsonValue) -> Result<ChannelConfig, Box<dyn Error>> {
     Ok(ChannelConfig {
         active: json["active"].as_bool().ok_or("Failed to parse \"active\"")?,
         name: json["name"].as_str().ok_or("Failed to parse \"name\"")?.to_owned(),
-        features: parse_features(&json["features"])?
+        features: parse_features(&json["features"])?,
+        cooldowns: parse_cooldowns(&json["cooldowns"])?,
+        permissions: parse_permissions(&json["permissions"])?,
+        events: parse_events(&json["events"])?
     })
 }
 
@@ -122,3 +453,157 @@ fn parse_features(json: &json::JsonValue) -> Result<Vec<FeatureKey>, Box<dyn Err
     }
     Ok(result)
 }
+
+fn parse_cooldowns(json: &json::JsonValue) -> Result<HashMap<String, Cooldown>, Box<dyn Error>> {
+    let mut result = HashMap::new();
+    if json.is_null() {
+        return Ok(result);
+    }
+    if !json.is_object() {
+        return Err("\"cooldowns\" is not an object".into());
+    }
+    for (command, entry) in json.entries() {
+        let cooldown = parse_cooldown(entry)
+            .map_err(|e| format!("Error parsing cooldown for {}: {}", command, e))?;
+        result.insert(command.to_owned(), cooldown);
+    }
+    Ok(result)
+}
+
+fn parse_cooldown(json: &json::JsonValue) -> Result<Cooldown, Box<dyn Error>> {
+    Ok(Cooldown {
+        global: parse_seconds(&json["global"]).ok_or("Failed to parse \"global\"")?,
+        channel: parse_seconds(&json["channel"]).ok_or("Failed to parse \"channel\"")?,
+        user: parse_seconds(&json["user"]).ok_or("Failed to parse \"user\"")?,
+        notify: json["notify"].as_bool().unwrap_or(false)
+    })
+}
+
+fn parse_seconds(json: &json::JsonValue) -> Option<Option<Duration>> {
+    if json.is_null() {
+        return Some(None);
+    }
+    json.as_f64()
+        .filter(|s| *s >= 0.0)
+        .map(|s| Some(Duration::from_secs_f64(s)))
+}
+
+fn parse_permissions(json: &json::JsonValue) -> Result<HashMap<String, Permission>, Box<dyn Error>> {
+    let mut result = HashMap::new();
+    if json.is_null() {
+        return Ok(result);
+    }
+    if !json.is_object() {
+        return Err("\"permissions\" is not an object".into());
+    }
+    for (key, entry) in json.entries() {
+        let level = Permission::parse(entry.as_str().ok_or("Failed to parse \"permissions\"")?)?;
+        result.insert(key.to_owned(), level);
+    }
+    Ok(result)
+}
+
+fn parse_events(json: &json::JsonValue) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut result = HashMap::new();
+    if json.is_null() {
+        return Ok(result);
+    }
+    if !json.is_object() {
+        return Err("\"events\" is not an object".into());
+    }
+    for (event, template) in json.entries() {
+        let template = template.as_str().ok_or(format!("Failed to parse template for event {}", event))?;
+        result.insert(event.to_owned(), template.to_owned());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_default_owner_for_old_configs() {
+        let config = from_json_string(r#"{"channels": []}"#).unwrap();
+        assert_eq!(config.owners, vec![DEFAULT_OWNER.to_owned()]);
+        let config = from_json_string(r#"{"owners": [], "channels": []}"#).unwrap();
+        assert!(config.owners.is_empty());
+        let config = from_json_string(r#"{"owners": ["42", "someone"], "channels": []}"#).unwrap();
+        assert_eq!(config.owners, vec!["42".to_owned(), "someone".to_owned()]);
+        assert!(from_json_string(r#"{"owners": [42], "channels": []}"#).is_err());
+    }
+
+    #[test]
+    fn save_keeps_keys_it_does_not_know() {
+        let path = crate::test_support::temp_dir("config").join("ircconfig.json");
+        std::fs::write(&path, r#"{
+            "owners": ["42"],
+            "notes": "hand edited",
+            "connection": {"nickname": "bot", "proxy": "socks5://localhost"},
+            "cooldowns": {"!ping": {"global": 5}},
+            "channels": [
+                {"name": "#one", "active": true, "features": [], "comment": "main channel", "events": {"raid": "hi"}},
+                {"name": "#two", "active": false, "features": ["ping"]}
+            ]
+        }"#).unwrap();
+        let mut config = from_json(&path).unwrap();
+        config.cooldowns.clear();
+        config.channels[0].events.clear();
+        config.channel_mut("#two").active = true;
+        save(&config, &path).unwrap();
+
+        let saved = json::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["notes"], "hand edited");
+        assert_eq!(saved["connection"]["proxy"], "socks5://localhost");
+        assert_eq!(saved["channels"][0]["comment"], "main channel");
+        assert_eq!(saved["channels"][1]["active"], true);
+        assert!(!saved.has_key("cooldowns"));
+        assert!(!saved["channels"][0].has_key("events"));
+        assert_eq!(from_json(&path).unwrap().owners, vec!["42".to_owned()]);
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn parses_connection_over_defaults() {
+        let config = from_json_string(r#"{"channels": []}"#).unwrap();
+        assert_eq!(config.connection.server, "irc.twitch.tv");
+        assert_eq!(config.connection.port, 6667);
+
+        let config = from_json_string(r#"{"channels": [], "connection": {"port": 6697, "use_tls": true,
+            "capabilities": ["twitch.tv/tags"]}}"#).unwrap();
+        let connection = config.connection;
+        assert_eq!((connection.port, connection.use_tls), (6697, true));
+        assert_eq!(connection.capabilities, vec!["twitch.tv/tags".to_owned()]);
+        assert_eq!(connection.nickname, "nichePenguin");
+        assert_eq!(connection.queue_delay_ms, 850);
+
+        assert!(from_json_string(r#"{"channels": [], "connection": {"port": "6697"}}"#).is_err());
+        assert!(from_json_string(r#"{"channels": [], "connection": {"port": 70000}}"#).is_err());
+    }
+
+    #[test]
+    fn environment_overrides_connection() {
+        let connection = Connection::default().with_vars(vars(&[])).unwrap();
+        assert_eq!(connection.nickname, "nichePenguin");
+
+        let connection = Connection { port: 1, ..Connection::default() }.with_vars(vars(&[
+            ("NPBOT_NICKNAME", "testbot"),
+            ("NPBOT_SERVER", "127.0.0.1"),
+            ("NPBOT_PORT", "6697"),
+            ("NPBOT_TLS", "true"),
+            ("NPBOT_QUEUE_DELAY_MS", "0"),
+            ("NPBOT_CAPABILITIES", "twitch.tv/tags, ,twitch.tv/commands")
+        ])).unwrap();
+        assert_eq!((connection.nickname.as_str(), connection.server.as_str()), ("testbot", "127.0.0.1"));
+        assert_eq!((connection.port, connection.use_tls, connection.queue_delay_ms), (6697, true, 0));
+        assert_eq!(connection.capabilities, vec!["twitch.tv/tags".to_owned(), "twitch.tv/commands".to_owned()]);
+        assert_eq!(connection.max_age_ms, 60_000);
+
+        assert!(Connection::default().with_vars(vars(&[("NPBOT_PORT", "http")])).is_err());
+        assert!(Connection::default().with_vars(vars(&[("NPBOT_TLS", "yes")])).is_err());
+    }
+}
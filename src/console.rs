@@ -0,0 +1,174 @@
+use std::{
+    error::Error,
+    path::PathBuf,
+    sync::{Arc, Mutex}
+};
+
+use async_trait::async_trait;
+use irc::client::prelude::{Command, Message};
+use irc::proto::message::Tag;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::config;
+use crate::irc::{ConfigWatch, Context, Providers};
+use crate::message_handler::handle;
+use crate::message_queue::Priority;
+use crate::outbound::Outbound;
+
+/// Prints replies to the terminal instead of sending them to IRC
+struct ConsoleOutbound;
+
+#[async_trait]
+impl Outbound for ConsoleOutbound {
+    async fn send(&self, message: Message, _priority: Priority) {
+        if let Command::PRIVMSG(channel, text) = &message.command {
+            println!("[{}] < {}", channel, text);
+        } else {
+            println!("< {}", message);
+        }
+    }
+
+    fn join(&self, channel: &str) -> Result<(), Box<dyn Error>> {
+        println!("JOIN {}", channel);
+        Ok(())
+    }
+
+    fn part(&self, channel: &str) -> Result<(), Box<dyn Error>> {
+        println!("PART {}", channel);
+        Ok(())
+    }
+}
+
+/// Who is talking and where, changed with `--user`, `--channel` and `--tag key=value`
+/// on the command line or `/user`, `/channel` and `/tag` in the console.
+/// Commands are enabled per channel as in the config, so unless the config lists #console
+/// only commands outside any feature, like !bot, answer there; pass `--channel` with a configured channel instead
+pub struct Session {
+    user: String,
+    channel: String,
+    tags: Vec<(String, String)>,
+    sent: u64
+}
+
+impl Session {
+    pub fn from_args(args: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut session = Session {
+            user: "console".to_owned(),
+            channel: "#console".to_owned(),
+            tags: Vec::new(),
+            sent: 0
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--console" => {},
+                "--user" | "--channel" | "--tag" => {
+                    let value = args.next().ok_or(format!("Missing value for {}", arg))?;
+                    session.set(&arg[2..], value)?;
+                },
+                _ => return Err(format!("Unknown argument: {}", arg).into())
+            }
+        }
+        Ok(session)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        match key {
+            "user" => self.user = value.to_owned(),
+            "channel" => self.channel = if value.starts_with('#') {
+                value.to_owned()
+            } else {
+                format!("#{}", value)
+            },
+            "tag" => {
+                let (name, value) = value.split_once('=').ok_or("Tags are set as key=value")?;
+                self.tags.retain(|t| t.0 != name);
+                if !value.is_empty() {
+                    self.tags.push((name.to_owned(), value.to_owned()));
+                }
+            },
+            _ => return Err(format!("Unknown setting: {}", key).into())
+        }
+        Ok(())
+    }
+
+    fn message(&mut self, text: &str) -> Result<Message, Box<dyn Error>> {
+        self.sent += 1;
+        let login = self.user.to_lowercase();
+        let mut tags = vec![
+            Tag("id".to_owned(), Some(format!("console-{}", self.sent))),
+            Tag("display-name".to_owned(), Some(self.user.clone())),
+            Tag("user-id".to_owned(), Some(login.clone())),
+        ];
+        for (name, value) in &self.tags {
+            tags.retain(|t| t.0 != *name);
+            tags.push(Tag(name.clone(), Some(value.clone())));
+        }
+        let prefix = format!("{0}!{0}@{0}.tmi.twitch.tv", login);
+        Ok(Message::with_tags(Some(tags), Some(prefix.as_str()), "PRIVMSG", vec![&self.channel[..], text])?)
+    }
+}
+
+/// Read chat lines from stdin and run them through the message handler
+pub async fn run(
+    mut session: Session,
+    safe_word: String,
+    config_path: PathBuf,
+    tarot_history: PathBuf,
+    noted_users: PathBuf,
+    providers: Providers,
+) -> Result<(), Box<dyn Error>> {
+    let config = Arc::new(Mutex::new(config::from_json(&config_path)?));
+    let outbound: Arc<dyn Outbound> = Arc::new(ConsoleOutbound);
+    ConfigWatch::start(config_path.clone()).attach(&outbound, &config);
+    let ctx = Context::new(
+        outbound,
+        Arc::clone(&config),
+        config_path,
+        providers,
+        tarot_history,
+        noted_users,
+        safe_word);
+
+    println!("Console mode, /user <name>, /channel <#name>, /tag <key=value> or /quit");
+    println!("Commands follow the features the config gives the channel, start with --channel <#name> to pick one");
+    warn_unconfigured(&config, &session.channel);
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "/quit" {
+            break;
+        }
+        if let Some(setting) = line.strip_prefix('/') {
+            let (key, value) = setting.split_once(' ').unwrap_or((setting, ""));
+            if let Err(e) = session.set(key, value.trim()) {
+                println!("{}", e);
+            } else if key == "channel" {
+                warn_unconfigured(&config, &session.channel);
+            }
+            continue;
+        }
+        let message = session.message(line)?;
+        println!("[{}] {} > {}", session.channel, session.user, line);
+        match handle(message, &ctx).await {
+            Ok(true) => {
+                println!("Exit requested");
+                break;
+            },
+            Ok(false) => {},
+            Err(e) => println!("Error handling message: {}", e)
+        }
+    }
+    Ok(())
+}
+
+/// Nothing is enabled in a channel the config doesn't list, say so instead of staying silent
+fn warn_unconfigured(config: &Mutex<config::Config>, channel: &str) {
+    let listed = config.lock().map(|c| c.channels.iter().any(|c| c.name == channel)).unwrap_or(true);
+    if !listed {
+        println!("{} is not in the config, only commands outside any feature answer there", channel);
+    }
+}
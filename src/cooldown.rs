@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::Cooldown;
+
+#[derive(PartialEq, Eq, Hash)]
+enum Scope {
+    Global(String),
+    Channel(String, String),
+    User(String, String),
+}
+
+struct Usage {
+    last_used: Instant,
+    /// When the cooldown it started runs out and the entry can go
+    expires: Instant,
+    notified: bool
+}
+
+pub enum Check {
+    Ready,
+    Waiting {
+        remaining: Duration,
+        notify: bool
+    }
+}
+
+/// Tracks the last use of each command per scope
+pub struct Cooldowns {
+    usage: Mutex<HashMap<Scope, Usage>>
+}
+
+impl Cooldowns {
+    pub fn new() -> Self {
+        Self {
+            usage: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Check whether a command may run and mark it as used if it can.
+    /// `notify` is only set for the first rejection in a cooldown window
+    pub fn check(&self, command: &str, channel: &str, user: &str, cooldown: &Cooldown) -> Check {
+        self.check_at(command, channel, user, cooldown, Instant::now())
+    }
+
+    fn check_at(&self, command: &str, channel: &str, user: &str, cooldown: &Cooldown, now: Instant) -> Check {
+        let scopes = [
+            (Scope::Global(command.to_owned()), cooldown.global),
+            (Scope::Channel(command.to_owned(), channel.to_owned()), cooldown.channel),
+            (Scope::User(command.to_owned(), user.to_owned()), cooldown.user),
+        ];
+
+        let usage = self.usage.lock();
+        if let Err(e) = &usage {
+            log::error!("Failed to get cooldown lock, assuming ready: {}", e);
+            return Check::Ready;
+        }
+        let mut usage = usage.unwrap();
+        // One entry per user who ever ran a command would pile up, expired ones tell nothing
+        usage.retain(|_, entry| entry.expires > now);
+
+        let mut remaining = Duration::ZERO;
+        let mut notify = false;
+        for (scope, duration) in &scopes {
+            let (Some(duration), Some(entry)) = (duration, usage.get_mut(scope)) else {
+                continue;
+            };
+            let passed = now - entry.last_used;
+            if passed < *duration {
+                remaining = remaining.max(*duration - passed);
+                if !entry.notified {
+                    entry.notified = true;
+                    notify = cooldown.notify;
+                }
+            }
+        }
+        if !remaining.is_zero() {
+            return Check::Waiting { remaining, notify };
+        }
+
+        for (scope, duration) in scopes {
+            if let Some(duration) = duration {
+                usage.insert(scope, Usage { last_used: now, expires: now + duration, notified: false });
+            }
+        }
+        Check::Ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cooldown(global: u64, channel: u64, user: u64) -> Cooldown {
+        let secs = |s| if s == 0 { None } else { Some(Duration::from_secs(s)) };
+        Cooldown { global: secs(global), channel: secs(channel), user: secs(user), notify: true }
+    }
+
+    fn waiting(check: Check) -> Option<(u64, bool)> {
+        match check {
+            Check::Ready => None,
+            Check::Waiting { remaining, notify } => Some((remaining.as_secs(), notify))
+        }
+    }
+
+    #[test]
+    fn applies_each_scope() {
+        let now = Instant::now();
+        let cooldowns = Cooldowns::new();
+        let global = cooldown(10, 0, 0);
+        assert_eq!(waiting(cooldowns.check_at("!a", "#one", "1", &global, now)), None);
+        assert_eq!(waiting(cooldowns.check_at("!a", "#two", "2", &global, now)), Some((10, true)));
+
+        let channel = cooldown(0, 10, 0);
+        assert_eq!(waiting(cooldowns.check_at("!b", "#one", "1", &channel, now)), None);
+        assert_eq!(waiting(cooldowns.check_at("!b", "#one", "2", &channel, now)), Some((10, true)));
+        assert_eq!(waiting(cooldowns.check_at("!b", "#two", "1", &channel, now)), None);
+
+        let user = cooldown(0, 0, 10);
+        assert_eq!(waiting(cooldowns.check_at("!c", "#one", "1", &user, now)), None);
+        assert_eq!(waiting(cooldowns.check_at("!c", "#two", "1", &user, now)), Some((10, true)));
+        assert_eq!(waiting(cooldowns.check_at("!c", "#one", "2", &user, now)), None);
+        assert_eq!(waiting(cooldowns.check_at("!d", "#one", "1", &user, now)), None);
+    }
+
+    #[test]
+    fn expires_and_notifies_once_per_window() {
+        let now = Instant::now();
+        let cooldowns = Cooldowns::new();
+        let both = cooldown(0, 5, 20);
+        assert_eq!(waiting(cooldowns.check_at("!a", "#one", "1", &both, now)), None);
+        let later = now + Duration::from_secs(1);
+        assert_eq!(waiting(cooldowns.check_at("!a", "#one", "1", &both, later)), Some((19, true)));
+        assert_eq!(waiting(cooldowns.check_at("!a", "#one", "1", &both, later)), Some((19, false)));
+        assert_eq!(waiting(cooldowns.check_at("!a", "#one", "2", &both, later)), Some((4, false)));
+
+        let expired = now + Duration::from_secs(20);
+        assert_eq!(waiting(cooldowns.check_at("!a", "#one", "1", &both, expired)), None);
+        assert_eq!(waiting(cooldowns.check_at("!a", "#one", "2", &both, expired)), Some((5, true)));
+
+        let quiet = Cooldown { notify: false, ..both };
+        assert_eq!(waiting(cooldowns.check_at("!b", "#one", "1", &quiet, now)), None);
+        assert_eq!(waiting(cooldowns.check_at("!b", "#one", "1", &quiet, now)), Some((20, false)));
+    }
+
+    #[test]
+    fn forgets_expired_uses() {
+        let now = Instant::now();
+        let cooldowns = Cooldowns::new();
+        let user = cooldown(0, 0, 10);
+        assert_eq!(waiting(cooldowns.check_at("!a", "#one", "1", &user, now)), None);
+        assert_eq!(waiting(cooldowns.check_at("!a", "#one", "2", &user, now + Duration::from_secs(5))), None);
+        assert_eq!(cooldowns.usage.lock().unwrap().len(), 2);
+
+        let later = now + Duration::from_secs(11);
+        assert_eq!(waiting(cooldowns.check_at("!a", "#one", "2", &user, later)), Some((4, true)));
+        let usage = cooldowns.usage.lock().unwrap();
+        assert_eq!(usage.len(), 1);
+        assert!(!usage.contains_key(&Scope::User("!a".to_owned(), "1".to_owned())));
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use json::object;
+use rand::{seq::IndexedRandom, Rng};
+
+use crate::armory::Sword;
+use crate::chatters::Chatter;
+use crate::commands::Command;
+use crate::config::FeatureKey;
+use crate::irc::Context;
+use crate::twitch::TwitchMessage;
+
+/// How long a challenge waits for `!accept`
+pub const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct Challenge {
+    challenger: Chatter,
+    /// Blade the challenger picked, a random one of theirs otherwise
+    blade: Option<i64>,
+    expires: Instant
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Record {
+    pub wins: u32,
+    pub losses: u32
+}
+
+/// Open challenges and the win/loss tally, every finished duel is appended to a JSON lines file
+pub struct Duels {
+    path: PathBuf,
+    /// By channel and login of the challenged user
+    challenges: Mutex<HashMap<(String, String), Challenge>>,
+    /// By login
+    records: Mutex<HashMap<String, Record>>
+}
+
+impl Duels {
+    pub fn open(path: PathBuf) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut records: HashMap<String, Record> = HashMap::new();
+        if path.exists() {
+            for (number, line) in std::fs::read_to_string(&path)?.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let json = match json::parse(line) {
+                    Ok(json) => json,
+                    Err(e) => {
+                        log::error!("Skipping broken line {} in {}: {}", number + 1, path.display(), e);
+                        continue;
+                    }
+                };
+                if let (Some(winner), Some(loser)) = (json["winner"].as_str(), json["loser"].as_str()) {
+                    records.entry(winner.to_lowercase()).or_default().wins += 1;
+                    records.entry(loser.to_lowercase()).or_default().losses += 1;
+                }
+            }
+        }
+        Ok(Self {
+            path,
+            challenges: Mutex::new(HashMap::new()),
+            records: Mutex::new(records)
+        })
+    }
+
+    /// Challenge `challenged` in `channel`, fails while someone else's challenge to them is still open
+    pub fn challenge(
+        &self,
+        channel: &str,
+        challenger: &Chatter,
+        challenged: &Chatter,
+        blade: Option<i64>,
+        now: Instant
+    ) -> Result<(), String> {
+        let mut challenges = self.challenges.lock().unwrap_or_else(|e| e.into_inner());
+        challenges.retain(|_, c| c.expires > now);
+        let key = (channel.to_owned(), challenged.login.clone());
+        if let Some(open) = challenges.get(&key) {
+            if open.challenger.login != challenger.login {
+                return Err(format!("{} already awaits an answer from {}", open.challenger.name, challenged.name));
+            }
+        }
+        challenges.insert(key, Challenge {
+            challenger: challenger.clone(),
+            blade,
+            expires: now + CHALLENGE_TIMEOUT
+        });
+        Ok(())
+    }
+
+    /// Take the open challenge to the `challenged` login, returns the challenger and their chosen blade
+    pub fn accept(&self, channel: &str, challenged: &str, now: Instant) -> Option<(Chatter, Option<i64>)> {
+        let mut challenges = self.challenges.lock().unwrap_or_else(|e| e.into_inner());
+        challenges.remove(&(channel.to_owned(), challenged.to_lowercase()))
+            .filter(|c| c.expires > now)
+            .map(|c| (c.challenger, c.blade))
+    }
+
+    pub fn record(&self, login: &str) -> Record {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.get(&login.to_lowercase()).copied().unwrap_or_default()
+    }
+
+    /// Journal the outcome and update both tallies
+    pub fn finish(&self, winner: (&Chatter, &Sword), loser: (&Chatter, &Sword)) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let line = object!(
+            winner: winner.0.login.clone(),
+            loser: loser.0.login.clone(),
+            winner_sword: winner.1.id,
+            loser_sword: loser.1.id,
+            at: chrono::Utc::now().to_rfc3339()
+        );
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all((json::stringify(line) + "\n").as_bytes())?;
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.entry(winner.0.login.clone()).or_default().wins += 1;
+        records.entry(loser.0.login.clone()).or_default().losses += 1;
+        Ok(())
+    }
+}
+
+/// `!duel @user [#id]` challenges someone with a blade from your armory, `!duel` alone shows your record
+pub struct Duel;
+
+#[async_trait]
+impl Command for Duel {
+    fn name(&self) -> &str {
+        "!duel"
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Duel
+    }
+
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let user = Chatter::of(message);
+        let reply = match args {
+            [] => {
+                let record = ctx.duels.record(&user.login);
+                format!("[💚] {} has won {} duels and lost {}.", user.name, record.wins, record.losses)
+            },
+            [target, rest @ ..] if rest.len() <= 1 => {
+                let id = rest.first().and_then(|s| s.trim_start_matches('#').parse::<i64>().ok());
+                challenge(ctx, message, &user, target, id).await
+            },
+            _ => "[💚] Usage: !duel @user [#id]".to_owned()
+        };
+        log::info!("{}: {}", message.channel, reply);
+        ctx.reply_or_send(message, reply.as_str()).await?;
+        Ok(false)
+    }
+}
+
+/// `!accept [#id]` answers an open challenge and fights it out
+pub struct Accept;
+
+#[async_trait]
+impl Command for Accept {
+    fn name(&self) -> &str {
+        "!accept"
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Duel
+    }
+
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let user = Chatter::of(message);
+        let id = args.first().and_then(|s| s.trim_start_matches('#').parse::<i64>().ok());
+        let reply = match ctx.duels.accept(&message.channel, &user.login, Instant::now()) {
+            Some((challenger, blade)) => fight(ctx, &challenger, blade, &user, id).await,
+            None => format!("[💚] No blade awaits yours, {}.", user.name)
+        };
+        log::info!("{}: {}", message.channel, reply);
+        ctx.reply_or_send(message, reply.as_str()).await?;
+        Ok(false)
+    }
+}
+
+async fn challenge(ctx: &Context, message: &TwitchMessage, user: &Chatter, target: &str, id: Option<i64>) -> String {
+    let login = target.trim_start_matches('@');
+    if login.eq_ignore_ascii_case(&user.login) {
+        return "[💚] You cross blades with your reflection. Nobody wins.".to_owned();
+    }
+    let blade = match ctx.swords.blade(user, id).await {
+        Some(blade) => blade,
+        None if id.is_some() => return "[💚] That blade is not yours to wield.".to_owned(),
+        None => return "[💚] Your hand has not yet taken to your sword...".to_owned()
+    };
+    let target = match ctx.chatter(login).await {
+        Some(target) if ctx.swords.blade(&target, None).await.is_some() => target,
+        _ => return format!("[💚] {} has no blade to answer with.", login)
+    };
+    match ctx.duels.challenge(&message.channel, user, &target, id, Instant::now()) {
+        Ok(()) => format!("[💚] {} challenges {} to a duel with {}! {}, type !accept [#id] within {} seconds.",
+            user.name, target.name, blade.title(), target.name, CHALLENGE_TIMEOUT.as_secs()),
+        Err(e) => format!("[💚] {}.", e)
+    }
+}
+
+async fn fight(ctx: &Context, challenger: &Chatter, challenger_blade: Option<i64>, defender: &Chatter, id: Option<i64>) -> String {
+    let defender_sword = match ctx.swords.blade(defender, id).await {
+        Some(sword) => sword,
+        None if id.is_some() => return "[💚] That blade is not yours to wield, the duel is off.".to_owned(),
+        None => return "[💚] Your hand has not yet taken to your sword, the duel is off.".to_owned()
+    };
+    // The chosen blade may have changed hands since the challenge
+    let challenger_sword = match challenger_blade {
+        Some(id) => ctx.swords.blade(challenger, Some(id)).await,
+        None => None
+    };
+    let challenger_sword = match challenger_sword {
+        Some(sword) => sword,
+        None => match ctx.swords.blade(challenger, None).await {
+            Some(sword) => sword,
+            None => return format!("[💚] {} has no blade left, the duel is off.", challenger.name)
+        }
+    };
+
+    let odds = challenger_sword.odds_against(&defender_sword);
+    let (winner, loser, winner_odds) = if rand::rng().random::<f64>() < odds {
+        ((challenger, &challenger_sword), (defender, &defender_sword), odds)
+    } else {
+        ((defender, &defender_sword), (challenger, &challenger_sword), 1.0 - odds)
+    };
+    if let Err(e) = ctx.duels.finish(winner, loser) {
+        log::error!("Failed to record a duel between {} and {}: {}", winner.0.login, loser.0.login, e);
+    }
+    let record = ctx.duels.record(&winner.0.login);
+    format!("[💚] {} ({}-{})", narrate(winner.1, loser.1, winner_odds), record.wins, record.losses)
+}
+
+/// How the fight went, depending on how likely the win was
+fn narrate(winner: &Sword, loser: &Sword, odds: f64) -> String {
+    let (w, wb, l, lb) = (&winner.owner, winner.title(), &loser.owner, loser.title());
+    let lines = if odds < 0.35 {
+        [
+            format!("Against all odds, {}'s {} slips past {}'s {} and finds its mark!", w, wb, l, lb),
+            format!("{}'s {} shatters on a lucky parry, and {} stands victorious with a humble {}!", l, lb, w, wb),
+            format!("Nobody bet on {}'s {}, yet {} is left kneeling, {} knocked from their grip!", w, wb, l, lb)
+        ]
+    } else if odds > 0.65 {
+        [
+            format!("{}'s {} makes short work of {}'s {}. It was never close.", w, wb, l, lb),
+            format!("{} barely raises their {} before {}'s {} sends it flying!", l, lb, w, wb),
+            format!("{}'s {} hums through the air, {}'s {} can only bend before it.", w, wb, l, lb)
+        ]
+    } else {
+        [
+            format!("Steel rings for what feels like hours, until {}'s {} finally outlasts {}'s {}!", w, wb, l, lb),
+            format!("{} and {} trade blow for blow, but the {} wins out over the {}!", w, l, wb, lb),
+            format!("A feint, a riposte, and {}'s {} is at {}'s throat. {}'s {} falls silent.", w, wb, l, l, lb)
+        ]
+    };
+    lines.choose(&mut rand::rng()).cloned().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn challenges_expire_and_records_persist() {
+        let path = test_support::temp_dir("duels").join("duels.jsonl");
+        let duels = Duels::open(path.clone()).unwrap();
+        let now = Instant::now();
+        let (first, third) = (Chatter::named("First"), Chatter::named("Third"));
+        // Localized display names only match by login
+        let second = Chatter { login: "second".to_owned(), name: "セカンド".to_owned() };
+        duels.challenge("#chan", &first, &second, Some(1), now).unwrap();
+        assert!(duels.challenge("#chan", &third, &second, None, now).is_err());
+        assert_eq!(duels.accept("#other", "second", now), None);
+        assert_eq!(duels.accept("#chan", "Second", now), Some((first.clone(), Some(1))));
+        assert_eq!(duels.accept("#chan", "Second", now), None);
+
+        duels.challenge("#chan", &first, &second, None, now).unwrap();
+        assert_eq!(duels.accept("#chan", "second", now + CHALLENGE_TIMEOUT), None);
+
+        let winner = Sword::deserialize(&test_support::sword(1, "First")).unwrap();
+        let loser = Sword::deserialize(&test_support::sword(2, "セカンド")).unwrap();
+        duels.finish((&first, &winner), (&second, &loser)).unwrap();
+        duels.finish((&second, &loser), (&first, &winner)).unwrap();
+        duels.finish((&first, &winner), (&second, &loser)).unwrap();
+        drop(duels);
+
+        let duels = Duels::open(path).unwrap();
+        assert_eq!(duels.record("first"), Record { wins: 2, losses: 1 });
+        assert_eq!(duels.record("Second"), Record { wins: 1, losses: 2 });
+        assert_eq!(duels.record("nobody"), Record::default());
+    }
+}
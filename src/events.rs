@@ -0,0 +1,225 @@
+use std::error::Error;
+
+use irc::client::prelude::{Command, Message};
+
+use crate::irc::Context;
+use crate::message_queue::Priority;
+use crate::twitch::tag;
+
+/// Twitch USERNOTICE, e.g. a subscription or a raid
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub channel: String,
+    /// Display name of the user who triggered the event
+    pub user: String,
+    /// Message the user attached, if any
+    pub message: Option<String>,
+    pub kind: EventKind
+}
+
+#[derive(Debug, Clone)]
+pub enum EventKind {
+    Sub { plan: String },
+    Resub { plan: String, months: u32, streak: Option<u32> },
+    SubGift { plan: String, recipient: String, months: u32 },
+    SubMysteryGift { plan: String, count: u32 },
+    Raid { viewers: u32 },
+    Announcement { color: Option<String> },
+    BitsBadgeTier { threshold: u64 },
+}
+
+impl EventKind {
+    /// `msg-id` of the event, also used as the key for templates in the config
+    pub fn key(&self) -> &'static str {
+        match self {
+            EventKind::Sub { .. } => "sub",
+            EventKind::Resub { .. } => "resub",
+            EventKind::SubGift { .. } => "subgift",
+            EventKind::SubMysteryGift { .. } => "submysterygift",
+            EventKind::Raid { .. } => "raid",
+            EventKind::Announcement { .. } => "announcement",
+            EventKind::BitsBadgeTier { .. } => "bitsbadgetier",
+        }
+    }
+}
+
+impl Event {
+    /// Parse a USERNOTICE, None for other commands and unsupported `msg-id`s
+    pub fn parse(message: &Message) -> Option<Self> {
+        let (channel, text) = match &message.command {
+            Command::Raw(command, args) if command == "USERNOTICE" =>
+                (args.first()?.clone(), args.get(1).cloned()),
+            _ => return None
+        };
+        let get = |name: &str| tag(message, name);
+        let number = |name: &str| get(name).and_then(|v| v.parse::<u32>().ok());
+        let plan = || plan_name(get("msg-param-sub-plan").as_deref());
+
+        let kind = match get("msg-id")?.as_str() {
+            "sub" => EventKind::Sub { plan: plan() },
+            "resub" => EventKind::Resub {
+                plan: plan(),
+                months: number("msg-param-cumulative-months").unwrap_or(1),
+                streak: number("msg-param-streak-months")
+            },
+            "subgift" => EventKind::SubGift {
+                plan: plan(),
+                recipient: get("msg-param-recipient-display-name")
+                    .or(get("msg-param-recipient-user-name"))?,
+                months: number("msg-param-months").unwrap_or(1)
+            },
+            "submysterygift" => EventKind::SubMysteryGift {
+                plan: plan(),
+                count: number("msg-param-mass-gift-count").unwrap_or(1)
+            },
+            "raid" => EventKind::Raid {
+                viewers: number("msg-param-viewerCount").unwrap_or(0)
+            },
+            "announcement" => EventKind::Announcement {
+                color: get("msg-param-color")
+            },
+            "bitsbadgetier" => EventKind::BitsBadgeTier {
+                threshold: get("msg-param-threshold").and_then(|v| v.parse().ok()).unwrap_or(0)
+            },
+            other => {
+                log::debug!("{}: ignoring USERNOTICE {}", channel, other);
+                return None;
+            }
+        };
+        let user = get("display-name")
+            .or(get("msg-param-displayName"))
+            .or(get("login"))
+            .unwrap_or("someone".to_owned());
+        Some(Self {
+            channel,
+            user,
+            message: text,
+            kind
+        })
+    }
+
+    /// Fill a template, placeholders are {user}, {message}, {plan}, {months}, {streak},
+    /// {recipient}, {count}, {viewers}, {color}, {threshold} and {tarot} for a freshly drawn card
+    pub fn render(&self, template: &str, ctx: &Context) -> String {
+        self.fill(template, || match ctx.tarot.draw() {
+            Ok((card, _)) => card,
+            Err(e) => {
+                log::error!("Error drawing a card for {}: {}", self.user, e);
+                "a card that slipped away".to_owned()
+            }
+        })
+    }
+
+    /// Substitute every placeholder in one pass over the template, so braces in what chatters
+    /// wrote, like their message, are never expanded. Unknown placeholders are left as they are
+    fn fill(&self, template: &str, mut tarot: impl FnMut() -> String) -> String {
+        let mut result = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            result.push_str(&rest[..open]);
+            let after = &rest[open..];
+            let value = after.find('}').and_then(|close| {
+                let key = &after[1..close];
+                let value = if key == "tarot" { Some(tarot()) } else { self.value(key) };
+                value.map(|v| (v, close))
+            });
+            match value {
+                Some((value, close)) => {
+                    result.push_str(&value);
+                    rest = &after[close + 1..];
+                },
+                None => {
+                    result.push('{');
+                    rest = &after[1..];
+                }
+            }
+        }
+        result.push_str(rest);
+        result
+    }
+
+    fn value(&self, key: &str) -> Option<String> {
+        let value = match (key, &self.kind) {
+            ("user", _) => self.user.clone(),
+            ("message", _) => self.message.clone().unwrap_or_default(),
+            ("plan", EventKind::Sub { plan })
+            | ("plan", EventKind::Resub { plan, .. })
+            | ("plan", EventKind::SubGift { plan, .. })
+            | ("plan", EventKind::SubMysteryGift { plan, .. }) => plan.clone(),
+            ("months", EventKind::Resub { months, .. })
+            | ("months", EventKind::SubGift { months, .. }) => months.to_string(),
+            ("streak", EventKind::Resub { streak, .. }) => streak.map(|s| s.to_string()).unwrap_or_default(),
+            ("recipient", EventKind::SubGift { recipient, .. }) => recipient.clone(),
+            ("count", EventKind::SubMysteryGift { count, .. }) => count.to_string(),
+            ("viewers", EventKind::Raid { viewers }) => viewers.to_string(),
+            ("color", EventKind::Announcement { color }) => color.clone().unwrap_or_default(),
+            ("threshold", EventKind::BitsBadgeTier { threshold }) => threshold.to_string(),
+            _ => return None
+        };
+        Some(value)
+    }
+}
+
+fn plan_name(plan: Option<&str>) -> String {
+    match plan {
+        Some("Prime") => "Prime".to_owned(),
+        Some("1000") => "Tier 1".to_owned(),
+        Some("2000") => "Tier 2".to_owned(),
+        Some("3000") => "Tier 3".to_owned(),
+        Some(other) => other.to_owned(),
+        None => "Tier 1".to_owned()
+    }
+}
+
+/// Answer an event with the template configured for its channel, if any
+pub async fn handle(event: Event, ctx: &Context) -> Result<(), Box<dyn Error>> {
+    log::info!("{}: {} {:?}", event.channel, event.user, event.kind);
+    let template = if let Some(template) = ctx.event_template(&event.channel, event.kind.key()) {
+        template
+    } else {
+        return Ok(());
+    };
+    let reply = event.render(&template, ctx);
+    ctx.send(&event.channel, reply.as_str(), Priority::Ambient).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Option<Event> {
+        Event::parse(&raw.parse::<Message>().unwrap())
+    }
+
+    #[test]
+    fn parses_user_notices() {
+        let event = parse("@display-name=Some\\sOne;login=someone;msg-id=resub;msg-param-cumulative-months=12;\
+            msg-param-sub-plan=2000 :tmi.twitch.tv USERNOTICE #chan :still here").unwrap();
+        assert_eq!((event.channel.as_str(), event.user.as_str()), ("#chan", "Some One"));
+        assert_eq!(event.message.as_deref(), Some("still here"));
+        assert!(matches!(event.kind, EventKind::Resub { ref plan, months: 12, streak: None } if plan == "Tier 2"));
+
+        let event = parse("@login=someone;msg-id=raid;msg-param-viewerCount=30 :tmi.twitch.tv USERNOTICE #chan").unwrap();
+        assert_eq!(event.user, "someone");
+        assert_eq!(event.message, None);
+        assert!(matches!(event.kind, EventKind::Raid { viewers: 30 }));
+
+        assert!(parse("@msg-id=subgift;msg-param-sub-plan=1000 :tmi.twitch.tv USERNOTICE #chan").is_none());
+        assert!(parse("@msg-id=ritual :tmi.twitch.tv USERNOTICE #chan").is_none());
+        assert!(parse(":someone!someone@someone.tmi.twitch.tv PRIVMSG #chan :hi").is_none());
+    }
+
+    #[test]
+    fn renders_chatter_text_verbatim() {
+        let event = parse("@display-name=someone;msg-id=resub;msg-param-cumulative-months=3;msg-param-sub-plan=Prime \
+            :tmi.twitch.tv USERNOTICE #chan :{tarot} {plan} {months").unwrap();
+        let mut draws = 0;
+        let rendered = event.fill("{user} {plan} x{months} {bogus}: {message} {tarot}", || {
+            draws += 1;
+            "The Moon".to_owned()
+        });
+        assert_eq!(rendered, "someone Prime x3 {bogus}: {tarot} {plan} {months The Moon");
+        assert_eq!(draws, 1);
+    }
+}
@@ -1,12 +1,90 @@
 use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
 use std::time::Duration;
 use reqwest::{
     Client,
+    RequestBuilder,
     Url,
-    header::{HeaderMap, HeaderValue, AUTHORIZATION}
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, RETRY_AFTER}
 };
 
+/// First retry waits this long, every next one twice as long up to `MAX_RETRY_DELAY`
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum GatewayError {
+    /// Request could not be built or the connection failed
+    Transport(reqwest::Error),
+    Timeout,
+    /// Non-success status other than the auth ones
+    Status { status: u16, body: String, retry_after: Option<Duration> },
+    /// Body is not the JSON we expected
+    Decode(String),
+    /// The gateway doesn't accept our secret
+    Auth { status: u16, body: String },
+}
+
+impl GatewayError {
+    /// Worth another attempt. Requests that aren't idempotent are only repeated when they surely
+    /// didn't reach the gateway or it turned them away unprocessed, other 5xx may have left a sword behind
+    pub fn retryable(&self, idempotent: bool) -> bool {
+        match self {
+            GatewayError::Transport(e) => e.is_connect() || (idempotent && !e.is_builder()),
+            GatewayError::Timeout => idempotent,
+            GatewayError::Status { status, .. } => matches!(status, 429 | 503) || (idempotent && *status >= 500),
+            GatewayError::Decode(_) | GatewayError::Auth { .. } => false,
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            GatewayError::Status { retry_after, .. } => *retry_after,
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GatewayError::Transport(e) => write!(f, "Gateway unreachable: {}", e),
+            GatewayError::Timeout => write!(f, "Gateway timed out"),
+            GatewayError::Status { status, body, .. } => write!(f, "Gateway answered {}: {}", status, body),
+            GatewayError::Decode(e) => write!(f, "Unexpected gateway response: {}", e),
+            GatewayError::Auth { status, body } => write!(f, "Gateway refused our secret ({}): {}", status, body),
+        }
+    }
+}
+
+impl Error for GatewayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GatewayError::Transport(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<reqwest::Error> for GatewayError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            GatewayError::Timeout
+        } else if e.is_decode() {
+            GatewayError::Decode(e.to_string())
+        } else {
+            GatewayError::Transport(e)
+        }
+    }
+}
+
+impl From<json::Error> for GatewayError {
+    fn from(e: json::Error) -> Self {
+        GatewayError::Decode(e.to_string())
+    }
+}
+
 pub struct Gateway {
     client: Client,
     base_url: Url,
@@ -25,85 +103,139 @@ impl Gateway {
             .build()?;
         Ok(Self {
             base_url: Url::parse(url.as_str())?,
-            retry_count: 10,
+            retry_count: 5,
             client
         })
     }
 
-    pub async fn get_text(&self, path: &str, query_params: HashMap<&str, String>) -> Result<String, Box<dyn Error>> {
-        //log::debug!("Get: {} {:?}", path, query_params);
-        let mut url = self.base_url.join(path)?;
+    fn url(&self, path: &str) -> Result<Url, GatewayError> {
+        self.base_url.join(path).map_err(|e| GatewayError::Decode(format!("Invalid path {}: {}", path, e)))
+    }
+
+    pub async fn get_text(&self, path: &str, query_params: HashMap<&str, String>) -> Result<String, GatewayError> {
+        let mut url = self.url(path)?;
         {
             let mut query = url.query_pairs_mut();
             for (k, v) in query_params {
                 query.append_pair(k, v.as_str());
             }
         }
-        let mut attempts = 0;
-        let mut err = None;
-        while attempts < self.retry_count  {
-            attempts += 1;
-            let resp = self.client.get(url.clone()).send().await;
-            match resp {
-                Ok(resp) => {
-                    match resp.text().await {
-                        Ok(text) => {
-                            //log::debug!("Got: {}", text);
-                            return Ok(text)
-                        }
-                        Err(e) => {
-                            log::error!("Failed to read body: {}", e);
-                            err = Some(e)
-                        }
-                    }
-                },
-                Err(e) => {
-                    log::error!("Get failed: {}", e);
-                    err = Some(e)
-                }
-            }
-            log::info!("Retry {} out of {}..", attempts, self.retry_count)
-        }
-        return Err(Box::new(err.unwrap()))
+        self.request(|| self.client.get(url.clone()), true).await
     }
 
-    pub async fn get(&self, path: &str, query_params: HashMap<&str, String>) -> Result<json::JsonValue, Box<dyn Error>> {
+    pub async fn get(&self, path: &str, query_params: HashMap<&str, String>) -> Result<json::JsonValue, GatewayError> {
         Ok(json::parse(self.get_text(path, query_params).await?.as_str())?)
     }
 
-    pub async fn post(&self, path: &str, body: json::JsonValue) -> Result<Option<json::JsonValue>, Box<dyn Error>> {
-        let url = self.base_url.join(path)?;
+    pub async fn post(&self, path: &str, body: json::JsonValue) -> Result<Option<json::JsonValue>, GatewayError> {
+        let url = self.url(path)?;
+        let text = self.request(|| self.client.post(url.clone()).body(json::stringify(body.clone())), false).await?;
+        if text.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(json::parse(text.as_str())?))
+        }
+    }
+
+    /// Sets the given fields, sending the same values twice does no harm so it is retried like a GET
+    pub async fn patch(&self, path: &str, body: json::JsonValue) -> Result<Option<json::JsonValue>, GatewayError> {
+        let url = self.url(path)?;
+        let text = self.request(|| self.client.patch(url.clone()).body(json::stringify(body.clone())), true).await?;
+        if text.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(json::parse(text.as_str())?))
+        }
+    }
+
+    /// Send a request, retrying only what can succeed on another attempt
+    async fn request<F>(&self, build: F, idempotent: bool) -> Result<String, GatewayError>
+    where
+        F: Fn() -> RequestBuilder
+    {
         let mut attempts = 0;
-        let mut err = None;
-        while attempts < self.retry_count {
+        loop {
             attempts += 1;
-            match  self.client
-                .post(url.clone())
-                .body(json::stringify(body.clone()))
-                .send().await
-            {
-            Ok(resp) => {
-                match resp.text().await {
-                    Ok(text) => {
-                        if text.is_empty() {
-                            return Ok(None)
-                        } else {
-                            return Ok(Some(json::parse(text.as_str())?))
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("Failed to read body: {}", e);
-                        return Ok(None)
-                    }
-                }
+            match Self::attempt(build()).await {
+                Ok(text) => return Ok(text),
+                Err(e) if attempts < self.retry_count && e.retryable(idempotent) => {
+                    let delay = e.retry_after().unwrap_or_else(|| RETRY_DELAY
+                        .saturating_mul(2u32.saturating_pow(attempts as u32 - 1)))
+                        .min(MAX_RETRY_DELAY);
+                    log::error!("{}", e);
+                    log::info!("Retry {} out of {} in {}ms..", attempts, self.retry_count, delay.as_millis());
+                    tokio::time::sleep(delay).await;
                 },
-                Err(e) => {
-                    log::error!("Post failed");
-                    err = Some(e)
-                }
+                Err(e) => return Err(e)
             }
-            log::info!("Retry {} out of {}..", attempts, self.retry_count)
         }
-        return Err(Box::new(err.unwrap()))
+    }
+
+    async fn attempt(request: RequestBuilder) -> Result<String, GatewayError> {
+        let response = request.send().await?;
+        let status = response.status();
+        let retry_after = response.headers().get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_secs);
+        let body = response.text().await?;
+        if status.is_success() {
+            return Ok(body);
+        }
+        Err(match status.as_u16() {
+            401 | 403 => GatewayError::Auth { status: status.as_u16(), body },
+            status => GatewayError::Status { status, body, retry_after }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, FakeGateway};
+
+    #[tokio::test]
+    async fn gets_pages_from_gateway() {
+        let gateway = FakeGateway::start((1..=3).map(|id| test_support::sword(id, "owner")).collect()).await;
+        let client = Gateway::init(gateway.url(), "secret".to_owned()).unwrap();
+
+        let params = HashMap::from([("page", 2.to_string()), ("per_page", 2.to_string())]);
+        let page = client.get("/armory", params).await.unwrap();
+        assert_eq!(page["data"].len(), 1);
+        assert_eq!(page["data"][0]["id"], 3);
+        assert_eq!(page["meta"]["has_next"], false);
+    }
+
+    #[tokio::test]
+    async fn posts_to_gateway() {
+        let gateway = FakeGateway::start(Vec::new()).await;
+        let client = Gateway::init(gateway.url(), "secret".to_owned()).unwrap();
+
+        let response = client.post("/armory", test_support::sword(0, "owner")).await.unwrap();
+        assert_eq!(response.unwrap()["id"], 1);
+        assert_eq!(gateway.swords().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn fails_fast_on_client_errors() {
+        let gateway = FakeGateway::start(Vec::new()).await;
+        let client = Gateway::init(gateway.url(), "secret".to_owned()).unwrap();
+
+        let started = std::time::Instant::now();
+        let error = client.get("/missing", HashMap::new()).await.unwrap_err();
+        assert!(matches!(error, GatewayError::Status { status: 404, .. }));
+        let error = client.post("/missing", test_support::sword(0, "owner")).await.unwrap_err();
+        assert!(matches!(error, GatewayError::Status { status: 404, .. }));
+        assert!(started.elapsed() < RETRY_DELAY);
+    }
+
+    #[test]
+    fn retries_posts_only_when_they_were_turned_away() {
+        let status = |status| GatewayError::Status { status, body: String::new(), retry_after: None };
+        assert!(status(429).retryable(false) && status(503).retryable(false));
+        assert!(!status(500).retryable(false) && !status(502).retryable(false));
+        assert!(status(500).retryable(true) && status(502).retryable(true));
+        assert!(!status(404).retryable(true));
+        assert!(!GatewayError::Timeout.retryable(false));
     }
 }
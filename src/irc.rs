@@ -1,61 +1,206 @@
+use async_trait::async_trait;
 use futures::prelude::*;
 use futures::stream::FusedStream;
 use irc::client::prelude::{
     Config as IrcConfig,
-    Capability, Command, Message, Client
+    Command, Message, Client
 };
+use irc::proto::CapSubCommand;
 
 use std::{
     error::Error,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, Weak},
     path::PathBuf
 };
+use tokio::time::{Duration, Instant, sleep, sleep_until};
 
 use crate::armory::Swords;
-use crate::config::{self, Config, FeatureKey};
-use crate::message_handler::handle;
-use crate::message_queue;
-use crate::gateway::Gateway;
+use crate::commands::{self, Registry};
+use crate::config::{self, Config, Cooldown, FeatureKey};
+use crate::cooldown::Cooldowns;
+use crate::permission::Permission;
+use crate::twitch::{self, TwitchMessage};
+use crate::message_handler::{self, handle};
+use crate::message_queue::{self, MessageQueue, Priority, QueueStats};
+use crate::outbound::Outbound;
+use crate::store::ArmoryStore;
+use crate::duel::Duels;
+use crate::trade::Trades;
+use crate::chatters::{Chatter, Chatters};
 use crate::moon::Moon;
+use crate::keepalive::{Health, Keepalive};
+use crate::reconnect::{Fatal, Requested};
+use crate::shutdown::Shutdown;
+
+/// How long a shutdown waits for queued replies and for swords being saved
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time for the PARTs to make it out before the connection is dropped
+const PART_GRACE: Duration = Duration::from_millis(500);
+
+/// Everything the commands draw from, independent of where messages come from.
+/// Built once at startup and shared by every session, so caches and cooldowns survive reconnects
+#[derive(Clone)]
+pub struct Providers {
+    pub swords: Arc<Swords>,
+    pub tarot: Arc<np_tarot::Tarot>,
+    pub moon: Arc<Moon>,
+    pub armory: Arc<dyn ArmoryStore>,
+    pub duels: Arc<Duels>,
+    pub trades: Arc<Trades>,
+    pub chatters: Arc<Chatters>,
+    pub cooldowns: Arc<Cooldowns>
+}
 
 pub struct Context {
-    queue: Arc<message_queue::MessageQueue>,
-    pub swords: Swords,
-    pub moon: Moon,
-    pub tarot: np_tarot::Tarot,
+    outbound: Arc<dyn Outbound>,
+    pub swords: Arc<Swords>,
+    pub moon: Arc<Moon>,
+    pub tarot: Arc<np_tarot::Tarot>,
     pub tarot_history: PathBuf,
     pub noted_users: PathBuf,
-    pub safe_word: String,
-    pub gateway: Arc<Gateway>,
-    config: Arc<Mutex<Config>>
+    pub commands: Registry,
+    pub armory: Arc<dyn ArmoryStore>,
+    pub duels: Arc<Duels>,
+    pub trades: Arc<Trades>,
+    pub chatters: Arc<Chatters>,
+    pub cooldowns: Arc<Cooldowns>,
+    config: Arc<Mutex<Config>>,
+    config_path: PathBuf
 }
 
 impl Context {
-    pub async fn reply_or_send(&self, reply_to: Message, text: &str) -> Result<(), Box<dyn Error>> {
-        let channel = if let Command::PRIVMSG(channel, _) = reply_to.command {
-            channel
-        } else {
-            return Err("No channel in to_reply message".into());
-        };
+    pub fn new(
+        outbound: Arc<dyn Outbound>,
+        config: Arc<Mutex<Config>>,
+        config_path: PathBuf,
+        providers: Providers,
+        tarot_history: PathBuf,
+        noted_users: PathBuf,
+        safe_word: String,
+    ) -> Self {
+        Self {
+            outbound,
+            swords: providers.swords,
+            moon: providers.moon,
+            tarot: providers.tarot,
+            armory: providers.armory,
+            duels: providers.duels,
+            trades: providers.trades,
+            chatters: providers.chatters,
+            tarot_history,
+            noted_users,
+            commands: message_handler::commands(safe_word),
+            cooldowns: providers.cooldowns,
+            config,
+            config_path
+        }
+    }
 
-        if let Some(Some(message_id)) = reply_to.tags
-            .into_iter().flatten()
-            .find(|t| t.0 == "id").map(|t| t.1)
-        {
+    pub async fn reply_or_send(&self, reply_to: &TwitchMessage, text: &str) -> Result<(), Box<dyn Error>> {
+        self.reply_with_priority(reply_to, text, Priority::Reply).await
+    }
+
+    pub async fn reply_with_priority(
+        &self,
+        reply_to: &TwitchMessage,
+        text: &str,
+        priority: Priority
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(message_id) = &reply_to.id {
             let reply = Message::with_tags(
                 Some(vec![
-                    irc::proto::message::Tag("reply-parent-msg-id".to_owned(), Some(message_id))
+                    irc::proto::message::Tag("reply-parent-msg-id".to_owned(), Some(message_id.clone()))
                 ]),
                 None,
                 "PRIVMSG",
-                vec![&channel[..], text])?;
-            self.queue.send(reply).await;
+                vec![&reply_to.channel[..], text])?;
+            self.outbound.send(reply, priority).await;
         } else {
-            self.queue.send(Command::PRIVMSG(channel.to_string(), text.to_string()).into()).await;
+            self.send(&reply_to.channel, text, priority).await;
+        }
+        Ok(())
+    }
+
+    pub async fn send(&self, channel: &str, text: &str, priority: Priority) {
+        self.outbound.send(Command::PRIVMSG(channel.to_string(), text.to_string()).into(), priority).await;
+    }
+
+    pub fn queue_stats(&self) -> Option<QueueStats> {
+        self.outbound.stats()
+    }
+
+    pub fn resume(&self, channel: &str) -> bool {
+        self.outbound.resume(channel)
+    }
+
+    /// Response template for a USERNOTICE event in a channel
+    pub fn event_template(&self, channel: &str, event: &str) -> Option<String> {
+        match self.config.lock() {
+            Ok(config) => config.channels.iter()
+                .find(|c| c.name == channel)
+                .and_then(|c| c.events.get(event))
+                .cloned(),
+            Err(e) => {
+                log::error!("Failed to get config lock, ignoring event: {}", e);
+                None
+            }
         }
+    }
+
+    pub fn cooldown(&self, command: &str, channel: &str) -> Option<Cooldown> {
+        match self.config.lock() {
+            Ok(config) => config.cooldown(command, channel).cloned(),
+            Err(e) => {
+                log::error!("Failed to get config lock, assuming no cooldown: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Apply a change to the config, write it back to disk and join or part channels accordingly.
+    /// The lock is held throughout, so a reload from the file watcher can't interleave
+    pub fn edit_config<F>(&self, edit: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnOnce(&mut Config) -> Result<(), Box<dyn Error>>
+    {
+        let mut config = self.config.lock().map_err(|e| format!("Failed to obtain config lock: {}", e))?;
+        let mut new_config = config.clone();
+        edit(&mut new_config)?;
+        config::save(&new_config, &self.config_path)?;
+        replace_config(self.outbound.as_ref(), &mut config, new_config);
         Ok(())
     }
 
+    /// Who `@login` refers to: someone seen in chat or an owner in the armory, None for anyone else
+    pub async fn chatter(&self, login: &str) -> Option<Chatter> {
+        let owner = self.swords.collection(login.trim_start_matches('@')).await.map(|c| c.owner);
+        self.chatters.resolve(login, owner)
+    }
+
+    /// Permission level of the message sender
+    pub fn permission(&self, message: &TwitchMessage) -> Permission {
+        match self.config.lock() {
+            Ok(config) => Permission::of(message, &config.owners),
+            Err(e) => {
+                log::error!("Failed to get config lock, assuming no privileges: {}", e);
+                Permission::Everyone
+            }
+        }
+    }
+
+    /// Permission level required to run a command in a channel
+    pub fn required_permission(&self, command: &dyn commands::Command, channel: &str) -> Permission {
+        let feature = command.feature();
+        match self.config.lock() {
+            Ok(config) => config.permission(command.name(), &feature, channel).unwrap_or_else(|| command.permission()),
+            Err(e) => {
+                log::error!("Failed to get config lock, assuming owner only: {}", e);
+                Permission::Owner
+            }
+        }
+    }
+
     pub fn is_enabled(&self, key: FeatureKey, channel: &str) -> bool{
         if let FeatureKey::Any = key {
             return true;
@@ -84,25 +229,79 @@ impl Context {
             false
         }
     }
+
+    /// Leave every active channel
+    pub fn part_all(&self) {
+        let channels = match self.config.lock() {
+            Ok(config) => config.channels.iter()
+                .filter(|c| c.active)
+                .map(|c| c.name.clone())
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                log::error!("Failed to get config lock, not parting: {}", e);
+                return;
+            }
+        };
+        for channel in channels {
+            if let Err(e) = self.outbound.part(&channel) {
+                log::error!("Error parting channel {}: {}", channel, e);
+            }
+        }
+    }
+}
+
+struct IrcOutbound {
+    queue: Arc<MessageQueue>,
+    client: Arc<Mutex<Client>>
+}
+
+#[async_trait]
+impl Outbound for IrcOutbound {
+    async fn send(&self, message: Message, priority: Priority) {
+        self.queue.send_with_priority(message, priority).await;
+    }
+
+    fn join(&self, channel: &str) -> Result<(), Box<dyn Error>> {
+        let client = self.client.lock().map_err(|e| format!("Failed to obtain client lock: {}", e))?;
+        Ok(client.send_join(channel)?)
+    }
+
+    fn part(&self, channel: &str) -> Result<(), Box<dyn Error>> {
+        let client = self.client.lock().map_err(|e| format!("Failed to obtain client lock: {}", e))?;
+        Ok(client.send_part(channel)?)
+    }
+
+    fn stats(&self) -> Option<QueueStats> {
+        Some(self.queue.stats())
+    }
+
+    fn resume(&self, channel: &str) -> bool {
+        self.queue.resume(channel)
+    }
 }
 
 pub async fn connect(
     token: &str,
     safe_word: String,
     config_path: PathBuf,
+    config_watch: &ConfigWatch,
     tarot_history: PathBuf,
     noted_users: PathBuf,
-    swords: Swords,
-    tarot: np_tarot::Tarot,
-    moon: Moon,
-    gateway: Arc<Gateway>,
+    providers: Providers,
+    mut shutdown: Shutdown,
 ) -> Result<tokio::task::JoinHandle<Result<(), Box<dyn Error + Send + Sync>>>, Box<dyn Error>> {
+    let main_config = config::from_json(&config_path)
+        .map_err(|e| Fatal(format!("Failed to read {}: {}", config_path.display(), e)))?;
+    let connection = main_config.connection.clone().with_env().map_err(|e| Fatal(e.to_string()))?;
+    log::info!("Connecting to {}:{} as {} (tls: {})",
+        connection.server, connection.port, connection.nickname, connection.use_tls);
+
     let config = IrcConfig {
-        nickname: Some("nichePenguin".to_owned()),
-        server: Some("irc.twitch.tv".to_owned()),
-        port: Some(6667),
+        nickname: Some(connection.nickname.clone()),
+        server: Some(connection.server.clone()),
+        port: Some(connection.port),
         password: Some(token.to_owned()),
-        use_tls: Some(false),
+        use_tls: Some(connection.use_tls),
         .. IrcConfig::default()
     };
 
@@ -110,11 +309,9 @@ pub async fn connect(
     let mut stream = client.stream()?;
 
     client.identify()?;
-    client.send_cap_req(&[
-        Capability::Custom("twitch.tv/membership"),
-        Capability::Custom("twitch.tv/tags")])?;
-
-    let main_config = config::from_json(&config_path)?;
+    if !connection.capabilities.is_empty() {
+        client.send(Command::CAP(None, CapSubCommand::REQ, None, Some(connection.capabilities.join(" "))))?;
+    }
 
     for channel in &main_config.channels {
         if channel.active {
@@ -125,45 +322,105 @@ pub async fn connect(
     }
 
     let main_config = Arc::new(Mutex::new(main_config));
-    let config_ref= Arc::clone(&main_config);
     let client = Arc::new(Mutex::new(client));
-    let client_ref= Arc::clone(&client);
-
-    let queue = Arc::new(message_queue::start(client, 850).await);
-    let ctx = Context {
-        queue,
-        moon,
-        swords,
-        tarot,
+
+    let queue = Arc::new(message_queue::start(Arc::clone(&client), &connection).await);
+    let nickname = connection.nickname.to_lowercase();
+    let outbound: Arc<dyn Outbound> = Arc::new(IrcOutbound {
+        queue: Arc::clone(&queue),
+        client: Arc::clone(&client)
+    });
+    let ping_interval = Duration::from_secs(connection.ping_interval_secs);
+    let ping_timeout = Duration::from_secs(connection.ping_timeout_secs);
+    let server = connection.server.clone();
+
+    config_watch.attach(&outbound, &main_config);
+
+    let ctx = Context::new(
+        outbound,
+        main_config,
+        config_path,
+        providers,
         tarot_history,
         noted_users,
-        safe_word,
-        gateway: gateway,
-        config: config_ref
-    };
-
-    log::debug!("Starting config watcher...");
-    np_utils::file_watch(config_path, 1000*3, Box::new(move |data| {
-        log::info!("Config updated");
-        if let Err(e) = update_config(Arc::clone(&client_ref), Arc::clone(&main_config), data) {
-            log::error!("Error parsing updated config: {}", e);
-        }
-    }));
+        safe_word);
 
     Ok(tokio::task::spawn( async move {
         log::info!("IRC loop started");
         let mut valid_exit = false;
-        while let Some(message) = stream.next().await {
-            if let Err(e) = message {
-                log::error!("Error receiving message: {}", e);
-                log::error!("Exiting...");
-                break;
-            }
-
-            let message = message?;
+        let mut failure: Option<Box<dyn Error + Send + Sync>> = None;
+        let mut keepalive = Keepalive::new(ping_interval, ping_timeout, Instant::now());
+        loop {
+            let message = tokio::select! {
+                message = stream.next() => message,
+                _ = sleep_until(keepalive.deadline()) => {
+                    match keepalive.check(Instant::now()) {
+                        Health::Alive => {},
+                        Health::Ping => {
+                            log::debug!("No traffic for {}s, sending PING", ping_interval.as_secs());
+                            let sent = client.lock()
+                                .map_err(|e| e.to_string())
+                                .and_then(|client| client.send(Command::PING(server.clone(), None)).map_err(|e| e.to_string()));
+                            if let Err(e) = sent {
+                                log::error!("Error sending PING: {}", e);
+                            }
+                        },
+                        Health::Dead(silence) => {
+                            failure = Some(format!("No answer from the server for {}s", silence.as_secs()).into());
+                            break;
+                        }
+                    }
+                    continue;
+                },
+                _ = shutdown.wait() => {
+                    // Keep polling the stream, it is what flushes outgoing messages
+                    let finish = shut_down(&ctx, &queue);
+                    tokio::pin!(finish);
+                    loop {
+                        tokio::select! {
+                            _ = &mut finish => break,
+                            message = stream.next() => if message.is_none() {
+                                break;
+                            }
+                        }
+                    }
+                    valid_exit = true;
+                    break;
+                }
+            };
+            let message = match message {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => {
+                    log::error!("Error receiving message: {}", e);
+                    log::error!("Exiting...");
+                    break;
+                },
+                None => break
+            };
+            keepalive.traffic(Instant::now());
 
-            if message.source_nickname().unwrap_or("unknown") == "nichepenguin" {
-                ctx.queue.reset_delay().await;
+            if message.source_nickname().unwrap_or("unknown") == nickname {
+                queue.reset_delay().await;
+            }
+            match &message.command {
+                Command::Raw(command, args) if command == "USERSTATE" => if let Some(channel) = args.first() {
+                    let moderator = twitch::tag(&message, "mod").is_some_and(|v| v == "1")
+                        || twitch::tag(&message, "badges").is_some_and(|b| b.contains("broadcaster/"));
+                    queue.set_moderator(channel, moderator);
+                    queue.confirm(channel);
+                },
+                Command::NOTICE(_, notice) if is_login_failure(notice) => {
+                    failure = Some(Fatal(format!("Twitch rejected the login: {}", notice)).into());
+                    break;
+                },
+                Command::Raw(command, _) if command == "RECONNECT" => {
+                    failure = Some(Requested.into());
+                    break;
+                },
+                Command::NOTICE(channel, notice) => if let Some(msg_id) = twitch::tag(&message, "msg-id") {
+                    queue.rejected(channel, &msg_id, notice);
+                },
+                _ => {}
             }
             let exit = handle(message, &ctx).await.map_err(|e| format!("Error handling message: {}", e))?;
             if exit {
@@ -173,10 +430,13 @@ pub async fn connect(
             }
         }
         log::info!("IRC loop exiting...");
-        ctx.queue.stop_loop();
+        queue.stop_loop();
         if valid_exit {
             return Ok(())
         }
+        if let Some(failure) = failure {
+            return Err(failure);
+        }
         if stream.is_terminated() {
             Err("Client stream terminated without a command, will retry".into())
         } else {
@@ -185,31 +445,196 @@ pub async fn connect(
     }))
 }
 
+/// Stop handling commands, let queued replies and sword posts finish, then leave the channels
+async fn shut_down(ctx: &Context, queue: &MessageQueue) {
+    log::info!("Shutting down with {} messages queued", queue.pending());
+    if !queue.drain(DRAIN_TIMEOUT).await {
+        log::error!("Dropping {} messages that didn't make it out in time", queue.pending());
+    }
+    ctx.swords.flush(FLUSH_TIMEOUT).await;
+    ctx.part_all();
+    sleep(PART_GRACE).await;
+}
+
+/// Twitch answers a bad or expired token with a NOTICE and closes the connection
+fn is_login_failure(notice: &str) -> bool {
+    notice.contains("Login authentication failed") || notice.contains("Improperly formatted auth")
+}
+
+type Attached = Option<(Weak<dyn Outbound>, Weak<Mutex<Config>>)>;
+
+/// Reloads the config whenever the file changes on disk. Started once, every connection attaches
+/// its outbound and config to it, held weakly so a finished connection is not kept alive
+#[derive(Clone)]
+pub struct ConfigWatch {
+    attached: Arc<Mutex<Attached>>
+}
+
+impl ConfigWatch {
+    pub fn start(config_path: PathBuf) -> Self {
+        log::debug!("Starting config watcher...");
+        let attached: Arc<Mutex<Attached>> = Arc::new(Mutex::new(None));
+        let watched = Arc::clone(&attached);
+        np_utils::file_watch(config_path, 1000*3, Box::new(move |data| {
+            let current = watched.lock().ok()
+                .and_then(|a| a.as_ref().and_then(|(outbound, config)| Some((outbound.upgrade()?, config.upgrade()?))));
+            let Some((outbound, config)) = current else {
+                log::debug!("Config updated while not connected");
+                return;
+            };
+            log::info!("Config updated");
+            if let Err(e) = update_config(outbound.as_ref(), &config, data) {
+                log::error!("Error parsing updated config: {}", e);
+            }
+        }));
+        Self { attached }
+    }
+
+    /// Apply reloads to this connection from now on
+    pub fn attach(&self, outbound: &Arc<dyn Outbound>, config: &Arc<Mutex<Config>>) {
+        let mut attached = self.attached.lock().unwrap_or_else(|e| e.into_inner());
+        *attached = Some((Arc::downgrade(outbound), Arc::downgrade(config)));
+    }
+}
+
 fn update_config(
-    client: Arc<std::sync::Mutex<Client>>,
-    config: Arc<std::sync::Mutex<Config>>,
+    outbound: &dyn Outbound,
+    config: &Mutex<Config>,
     data: String) -> Result<(), Box<dyn Error>>
 {
     let new_config = config::from_json_string(data.as_str())?;
+    apply_config(outbound, config, new_config)
+}
+
+fn apply_config(
+    outbound: &dyn Outbound,
+    config: &Mutex<Config>,
+    new_config: Config) -> Result<(), Box<dyn Error>>
+{
     let mut config = config.lock().map_err(|e| format!("Failed to obtain config lock: {}", e))?;
-    let (to_join, to_part) = config::channels_diff(&config, &new_config);
-    if to_join.len() != 0 || to_part.len() != 0 {
-        let client = client.lock().map_err(|e| format!("Failed to obtain client lock: {}", e))?;
-        for part in to_part {
-            let part = part.to_string();
-            log::debug!("PART {}", part);
-            if let Err(e) = client.send_part(&part) {
-                log::error!("Error parting from {}: {}", part, e)
-            }
+    replace_config(outbound, &mut config, new_config);
+    Ok(())
+}
+
+fn replace_config(outbound: &dyn Outbound, config: &mut Config, new_config: Config) {
+    let (to_join, to_part) = config::channels_diff(config, &new_config);
+    for part in to_part {
+        log::debug!("PART {}", part);
+        if let Err(e) = outbound.part(&part) {
+            log::error!("Error parting from {}: {}", part, e)
         }
-        for join in to_join {
-            let join = join.to_string();
-            log::debug!("JOIN {}", join);
-            if let Err(e) = client.send_join(&join) {
-                log::error!("Error joining to {}: {}", join, e)
-            }
+    }
+    for join in to_join {
+        log::debug!("JOIN {}", join);
+        if let Err(e) = outbound.join(&join) {
+            log::error!("Error joining to {}: {}", join, e)
         }
     }
     *config = new_config;
-    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, FakeIrcServer};
+
+    async fn start(name: &str, channels: &[(&str, &[&str])]) -> (
+        FakeIrcServer,
+        PathBuf,
+        tokio::task::JoinHandle<Result<(), Box<dyn Error + Send + Sync>>>
+    ) {
+        start_with(name, channels, Shutdown::manual().1).await
+    }
+
+    async fn start_with(name: &str, channels: &[(&str, &[&str])], shutdown: Shutdown) -> (
+        FakeIrcServer,
+        PathBuf,
+        tokio::task::JoinHandle<Result<(), Box<dyn Error + Send + Sync>>>
+    ) {
+        test_support::start_bot(name, channels, shutdown).await
+    }
+
+    #[tokio::test]
+    async fn registers_and_joins_active_channels() {
+        let (server, _, handle) = start("join", &[("#test", &["full"])]).await;
+        server.wait_for(0, |l| l == "JOIN #test").await.expect("Channel was not joined");
+        let received = server.received().await;
+        assert!(received.iter().any(|l| l.starts_with("PASS oauth:test")));
+        assert!(received.iter().any(|l| l == "NICK testbot"));
+        assert!(received.iter().any(|l| l.starts_with("CAP REQ") && l.contains("twitch.tv/tags")));
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn replies_to_enabled_commands_only() {
+        let (server, _, handle) = start("reply", &[("#test", &["ping"])]).await;
+        server.wait_for(0, |l| l == "JOIN #test").await.expect("Channel was not joined");
+
+        let skip = server.received().await.len();
+        server.privmsg("#test", "chatter", "!rice", &[("id", "first")]);
+        server.privmsg("#test", "chatter", "!ping hello", &[("id", "second")]);
+        let reply = server.wait_for(skip, |l| l.contains("PRIVMSG #test")).await.expect("No reply sent");
+        assert!(reply.contains("reply-parent-msg-id=second"));
+        assert!(reply.ends_with("pong hello"));
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn stops_for_a_reconnect_request() {
+        let (server, _, handle) = start("reconnect", &[("#test", &[])]).await;
+        server.wait_for(0, |l| l == "JOIN #test").await.expect("Channel was not joined");
+
+        server.send(":tmi.twitch.tv RECONNECT");
+        let result = tokio::time::timeout(test_support::TIMEOUT, handle).await
+            .expect("Loop did not exit")
+            .expect("Loop panicked");
+        assert!(result.is_err_and(|e| crate::reconnect::is_requested(&*e)));
+    }
+
+    #[tokio::test]
+    async fn parts_channels_on_shutdown() {
+        let (trigger, shutdown) = Shutdown::manual();
+        let (server, _, handle) = start_with("shutdown", &[("#test", &[]), ("#other", &[])], shutdown).await;
+        server.wait_for(0, |l| l == "JOIN #other").await.expect("Channel was not joined");
+
+        trigger.send(true).expect("Loop is gone");
+        let result = tokio::time::timeout(test_support::TIMEOUT, handle).await
+            .expect("Loop did not exit")
+            .expect("Loop panicked");
+        assert!(result.is_ok());
+        let received = server.received().await;
+        assert!(received.iter().any(|l| l == "PART #test"));
+        assert!(received.iter().any(|l| l == "PART #other"));
+    }
+
+    #[tokio::test]
+    async fn exits_on_safe_word_from_owner_only() {
+        let (server, _, handle) = start("exit", &[("#test", &["ping"])]).await;
+        server.wait_for(0, |l| l == "JOIN #test").await.expect("Channel was not joined");
+
+        let skip = server.received().await.len();
+        server.privmsg("#test", "chatter", "safeword", &[("user-id", "1")]);
+        // Messages are handled in order, so an answer to the ping means the safe word was ignored
+        server.privmsg("#test", "chatter", "!ping", &[("user-id", "1")]);
+        server.wait_for(skip, |l| l.contains("PRIVMSG #test") && l.contains("pong")).await.expect("Loop stopped for a chatter");
+        assert!(!handle.is_finished());
+
+        server.privmsg("#test", "owner", "safeword", &[("user-id", "2")]);
+        let result = tokio::time::timeout(test_support::TIMEOUT, handle).await
+            .expect("Loop did not exit")
+            .expect("Loop panicked");
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn joins_and_parts_on_config_reload() {
+        let (server, config_path, handle) = start("reload", &[("#test", &[])]).await;
+        server.wait_for(0, |l| l == "JOIN #test").await.expect("Channel was not joined");
+
+        let dir = config_path.parent().expect("Config has a directory").to_path_buf();
+        test_support::write_config(&dir, server.port, &[("#other", &[])]);
+        server.wait_for(0, |l| l == "JOIN #other").await.expect("New channel was not joined");
+        server.wait_for(0, |l| l == "PART #test").await.expect("Removed channel was not parted");
+        handle.abort();
+    }
 }
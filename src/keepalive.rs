@@ -0,0 +1,78 @@
+use tokio::time::{Duration, Instant};
+
+/// Tracks whether the server is still talking to us.
+/// After `interval` of silence a PING goes out, no answer within `timeout` means the connection is dead
+pub struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    last_traffic: Instant,
+    ping_sent: Option<Instant>
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Health {
+    Alive,
+    /// Quiet for too long, time to PING
+    Ping,
+    /// No PONG or any other traffic since the PING
+    Dead(Duration)
+}
+
+impl Keepalive {
+    pub fn new(interval: Duration, timeout: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            timeout,
+            last_traffic: now,
+            ping_sent: None
+        }
+    }
+
+    /// When `check` has something new to say
+    pub fn deadline(&self) -> Instant {
+        match self.ping_sent {
+            Some(sent) => sent + self.timeout,
+            None => self.last_traffic + self.interval
+        }
+    }
+
+    /// Any message counts, not just PONG
+    pub fn traffic(&mut self, now: Instant) {
+        self.last_traffic = now;
+        self.ping_sent = None;
+    }
+
+    pub fn check(&mut self, now: Instant) -> Health {
+        if now < self.deadline() {
+            return Health::Alive;
+        }
+        if self.ping_sent.is_some() {
+            Health::Dead(now - self.last_traffic)
+        } else {
+            self.ping_sent = Some(now);
+            Health::Ping
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pings_when_quiet_and_dies_without_an_answer() {
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut keepalive = Keepalive::new(10 * second, 5 * second, now);
+        assert_eq!(keepalive.check(now + 9 * second), Health::Alive);
+
+        keepalive.traffic(now + 9 * second);
+        assert_eq!(keepalive.check(now + 18 * second), Health::Alive);
+        assert_eq!(keepalive.check(now + 19 * second), Health::Ping);
+        assert_eq!(keepalive.deadline(), now + 24 * second);
+
+        keepalive.traffic(now + 20 * second);
+        assert_eq!(keepalive.check(now + 30 * second), Health::Ping);
+        assert_eq!(keepalive.check(now + 35 * second), Health::Dead(15 * second));
+    }
+}
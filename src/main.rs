@@ -1,33 +1,55 @@
 mod irc;
 mod config;
 mod message_handler;
+mod commands;
+mod cooldown;
+mod permission;
+mod admin;
+mod outbound;
+mod twitch;
+mod events;
+mod console;
+#[cfg(test)]
+mod test_support;
 mod message_queue;
 mod clonk_stat;
 mod armory;
+mod duel;
+mod trade;
+mod chatters;
 mod sexpr;
 mod gateway;
+mod store;
 mod moon;
+mod outbox;
+mod reconnect;
+mod keepalive;
+mod shutdown;
 
 use std::{
     error::Error,
     path::PathBuf,
     env::var,
     sync::Arc,
-    time::SystemTime
+    time::{Instant, SystemTime}
 };
 
 use np_utils::get_env_var;
 use log::LevelFilter;
 
-const RETRY_DELAY_MS: u64 = 1000;
+use reconnect::{Backoff, Fatal};
+use shutdown::Shutdown;
+
 const HISTORY_FILE: &str = "history.csv";
 const USERS_FILE: &str = "noted_users.txt";
 const ELVEN_FILE: &str = "language_elven.txt";
 const AFFINITY_FILE: &str = "affinity.csv";
 const CONFIG_FILE: &str = "ircconfig.json";
+const OUTBOX_FILE: &str = "armory_outbox.jsonl";
+const DUELS_FILE: &str = "duels.jsonl";
 
-fn setup_logger() -> Result<(), fern::InitError> {
-    fern::Dispatch::new()
+fn setup_logger(stdout: bool) -> Result<(), fern::InitError> {
+    let mut dispatch = fern::Dispatch::new()
         .format(|out, message, record| {
             out.finish(format_args!(
                 "[{} {} {}] {}",
@@ -41,8 +63,11 @@ fn setup_logger() -> Result<(), fern::InitError> {
         .level_for("reqwest", LevelFilter::Off)
         .level_for("hyper", LevelFilter::Off)
         .level_for("html5ever", LevelFilter::Off)
-        .level_for("selectors", LevelFilter::Off)
-        .chain(std::io::stdout())
+        .level_for("selectors", LevelFilter::Off);
+    if stdout {
+        dispatch = dispatch.chain(std::io::stdout());
+    }
+    dispatch
         .chain(fern::log_file("log.txt")?)
         .apply()?;
     Ok(())
@@ -50,93 +75,155 @@ fn setup_logger() -> Result<(), fern::InitError> {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>>{
-    setup_logger()?;
-    let mut handle = connect().await;
-    let mut attempts = 0;
-    let max_attempts = 5;
-    loop {
-        let restart = match handle {
-            Ok(result) => {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if args.iter().any(|a| a == "--console") {
+        setup_logger(false)?;
+        return console(&args).await;
+    }
+    setup_logger(true)?;
+    let mut backoff = Backoff::from_env()?;
+    let mut shutdown = Shutdown::listen();
+
+    log::debug!("Reading token");
+    let token = env("NPBOT_TOKEN")?;
+    log::debug!("Reading safeword");
+    let safe_word = env("NPBOT_SAFEWORD")?;
+    let history_file = PathBuf::from(get_env_var("NPBOT_HISTORY", HISTORY_FILE));
+    let noted_users = PathBuf::from(get_env_var("NPBOT_USERS", USERS_FILE));
+    let config_file = PathBuf::from(get_env_var("NPBOT_CONFIG", CONFIG_FILE));
+
+    let config_watch = irc::ConfigWatch::start(config_file.clone());
+    let providers = loop {
+        match providers().await {
+            Ok(providers) => break providers,
+            Err(e) => retry(&mut backoff, &mut shutdown, e).await?
+        }
+        if shutdown.requested() {
+            return Ok(());
+        }
+    };
+
+    while !shutdown.requested() {
+        let started = Instant::now();
+        let connection = irc::connect(
+            &token,
+            safe_word.clone(),
+            config_file.clone(),
+            &config_watch,
+            history_file.clone(),
+            noted_users.clone(),
+            providers.clone(),
+            shutdown.clone(),
+        ).await;
+        let error: Box<dyn Error> = match connection {
+            Ok(handle) => {
                 log::info!("Connected");
-                attempts = 0;
-                let result = result.await;
-                match result {
+                match handle.await {
                     Ok(Ok(_)) => {
-                        log::info!("Exiting main loop, bye!");
-                        false
-                    },
-                    Ok(Err(e)) => {
-                        log::error!("Unrecoverable error in IRC loop: {}", e);
-                        true
+                        log::info!("IRC loop finished");
+                        break;
                     },
-                    Err(join_error) => {
-                        log::error!("IRC loop panicked: {}", join_error);
-                        true
-                    }
+                    Ok(Err(e)) => e,
+                    Err(join_error) => format!("IRC loop panicked: {}", join_error).into()
                 }
             },
-            Err(e) => {
-                log::error!("Error connecting: {}", e);
-                true
-            }
+            Err(e) => e
         };
 
-        if restart {
-            if attempts >= max_attempts {
-                log::error!("Failed after {} attempts, exiting...", max_attempts);
-                return Err(format!("Failed to connect after {} attempts", max_attempts).into());
-            } else {
-                attempts += 1;
-                log::info!("Retrying in {}ms ... [{}/{}]", RETRY_DELAY_MS, attempts, max_attempts);
-            }
-        } else {
-            break;
+        if reconnect::is_requested(&*error) {
+            log::info!("{}, reconnecting now", error);
+            continue;
         }
-        tokio::time::sleep(tokio::time::Duration::from_millis(RETRY_DELAY_MS)).await;
-        handle = connect().await;
+        backoff.connected_for(started.elapsed());
+        retry(&mut backoff, &mut shutdown, error).await?;
     }
+    // A shutdown during the backoff skips the connection's own flush, swords drawn before it still need saving
+    providers.swords.flush(irc::FLUSH_TIMEOUT).await;
+    log::info!("Exiting main loop, bye!");
     Ok(())
 }
 
-async fn connect() -> Result<tokio::task::JoinHandle<Result<(), Box<dyn Error + Send + Sync>>>, Box<dyn Error>> {
-    log::debug!("Reading token");
-    let token = var("NPBOT_TOKEN")?;
-    log::debug!("Reading safeword");
-    let safe_word = var("NPBOT_SAFEWORD")?;
-    log::debug!("Reading gateway url");
-    let gateway = var("NPBOT_GATEWAY")?;
-    log::debug!("Reading gateway secret");
-    let gateway_secret = var("NPBOT_GATEWAY_KEY")?;
+/// Wait out the backoff after a failed attempt, fatal errors and running out of attempts end the bot.
+/// A shutdown cuts the wait short
+async fn retry(backoff: &mut Backoff, shutdown: &mut Shutdown, error: Box<dyn Error>) -> Result<(), Box<dyn Error>> {
+    if reconnect::is_fatal(&*error) {
+        log::error!("Fatal error, exiting: {}", error);
+        return Err(error);
+    }
+    log::error!("Attempt failed: {}", error);
+    let delay = match backoff.next_delay() {
+        Some(delay) => delay,
+        None => {
+            log::error!("Failed after {} attempts, exiting...", backoff.attempts());
+            return Err(format!("Failed to connect after {} attempts", backoff.attempts()).into());
+        }
+    };
+    log::info!("Retrying in {}ms ... [{}/{}]", delay.as_millis(), backoff.attempts(),
+        backoff.max_attempts().map(|max| max.to_string()).unwrap_or("∞".to_owned()));
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => {},
+        _ = shutdown.wait() => {}
+    }
+    Ok(())
+}
+
+/// Required env var, missing ones are fatal
+fn env(name: &str) -> Result<String, Box<dyn Error>> {
+    var(name).map_err(|e| Fatal(format!("{}: {}", name, e)).into())
+}
+
+async fn console(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let session = console::Session::from_args(args)?;
+    let safe_word = env("NPBOT_SAFEWORD")?;
+    let providers = providers().await?;
+    console::run(
+        session,
+        safe_word,
+        PathBuf::from(get_env_var("NPBOT_CONFIG", CONFIG_FILE)),
+        PathBuf::from(get_env_var("NPBOT_HISTORY", HISTORY_FILE)),
+        PathBuf::from(get_env_var("NPBOT_USERS", USERS_FILE)),
+        providers,
+    ).await
+}
+
+async fn providers() -> Result<irc::Providers, Box<dyn Error>> {
     log::debug!("Reading moon info url");
-    let moon_url = var("NPBOT_MOON_URL")?;
-    log::debug!("All secrets are red and kept safe");
+    let moon_url = env("NPBOT_MOON_URL")?;
 
     let affinity_file = get_env_var("NPBOT_AFFINITY", AFFINITY_FILE);
-    let tarot_provider = np_tarot::Tarot::new(PathBuf::from(affinity_file))?;
+    let tarot = np_tarot::Tarot::new(PathBuf::from(affinity_file))
+        .map_err(|e| Fatal(format!("Failed to load tarot: {}", e)))?;
 
-    let moon_provider = moon::init(moon_url)?;
+    let moon = moon::init(moon_url).map_err(|e| Fatal(format!("Invalid moon info url: {}", e)))?;
 
-    let gateway = Arc::new(gateway::Gateway::init(gateway, gateway_secret)?);
+    let armory = store::from_env(|| {
+        log::debug!("Reading gateway url");
+        let gateway = env("NPBOT_GATEWAY")?;
+        log::debug!("Reading gateway secret");
+        let gateway_secret = env("NPBOT_GATEWAY_KEY")?;
+        log::debug!("All secrets are red and kept safe");
+        gateway::Gateway::init(gateway, gateway_secret)
+            .map_err(|e| Fatal(format!("Invalid gateway settings: {}", e)).into())
+    })?;
 
     let elven = get_env_var("NPBOT_ELVEN", ELVEN_FILE);
-    let sword_provider = armory::Swords::new(
+    let outbox = get_env_var("NPBOT_OUTBOX", OUTBOX_FILE);
+    let swords = armory::Swords::new(
         PathBuf::from(elven),
-        Arc::clone(&gateway),
+        PathBuf::from(outbox),
+        Arc::clone(&armory),
     ).await.map_err(|e| e.to_string())?;
+    let duels = duel::Duels::open(PathBuf::from(get_env_var("NPBOT_DUELS", DUELS_FILE)))
+        .map_err(|e| Fatal(format!("Failed to load duels: {}", e)))?;
 
-    let history_file = get_env_var("NPBOT_HISTORY", HISTORY_FILE);
-    let noted_users = get_env_var("NPBOT_USERS", USERS_FILE);
-    let config_file = get_env_var("NPBOT_CONFIG", CONFIG_FILE);
-
-    irc::connect(
-        &token,
-        safe_word,
-        PathBuf::from(config_file),
-        PathBuf::from(history_file),
-        PathBuf::from(noted_users),
-        sword_provider,
-        tarot_provider,
-        moon_provider,
-        gateway,
-    ).await
+    Ok(irc::Providers {
+        swords: Arc::new(swords),
+        tarot: Arc::new(tarot),
+        moon: Arc::new(moon),
+        armory,
+        duels: Arc::new(duels),
+        trades: Arc::new(trade::Trades::new()),
+        chatters: Arc::new(chatters::Chatters::new()),
+        cooldowns: Arc::new(cooldown::Cooldowns::new())
+    })
 }
@@ -2,207 +2,437 @@ use std::path::PathBuf;
 use std::error::Error;
 use std::sync::Arc;
 
-use irc::client::prelude::{Message, Command};
+use async_trait::async_trait;
+use irc::client::prelude::Message;
+use crate::twitch::TwitchMessage;
+use crate::admin;
+use crate::armory::Query;
+use crate::duel;
+use crate::trade;
+use crate::commands::{Command, Registry};
 use crate::config::FeatureKey;
+use crate::cooldown::Check;
+use crate::events::{self, Event};
 use crate::irc::Context;
+use crate::message_queue::Priority;
+use crate::permission::Permission;
 use rand::prelude::*;
 
 const HISTORY_SEPARATOR: &str = ",";
 
-enum ParsedMessage {
-    Rice,
-    Tarot,
-    Moon,
-    Armory(Option<i64>),
-    Hmmm,
-    Mmmm,
-    BugAd,
-    VoidStranger,
-    Needle,
-    Ping(String),
-    Np(Vec<String>),
-    Ignore,
-    Exit
-}
-
-fn parse(input: &Message, ctx: &Context) -> (ParsedMessage, Option<String>, Option<FeatureKey>) {
-    if let Command::PRIVMSG(channel, text) = &input.command {
-        let (parsed, key) = if text.starts_with("!rice") {
-            (ParsedMessage::Rice, Some(FeatureKey::Rice))
-        } else if text == "!sbob-ad" {
-            (ParsedMessage::BugAd, Some(FeatureKey::BugAd))
-        } else if text == "!needle" || text == "!haystack"{
-            (ParsedMessage::Needle, Some(FeatureKey::Needle))
-        } else if text.starts_with("!ping") {
-            (ParsedMessage::Ping(text.clone()), Some(FeatureKey::Ping))
-        } else if text.split_whitespace().next().is_some_and(|s| s.starts_with("!armory")) {
-            (ParsedMessage::Armory(text
-                .trim()
-                .replace('#', "")
-                .split_whitespace()
-                .filter(|s| *s != "!armory")
-                .next()
-                .map(|s| s.parse::<i64>().ok()).flatten()),
-            Some(FeatureKey::Tarot))
-        } else if text.starts_with("!moon") {
-            (ParsedMessage::Moon, Some(FeatureKey::Moon))
-        } else if text.starts_with("!draw") {
-            (ParsedMessage::Tarot, Some(FeatureKey::Tarot))
-        } else if text.starts_with("!voidstranger") {
-            (ParsedMessage::VoidStranger, Some(FeatureKey::VoidStranger))
-        } else if text.starts_with("mmmm") {
-            (ParsedMessage::Mmmm, Some(FeatureKey::Mmmm))
-        } else if text.starts_with("hmmm") {
-            (ParsedMessage::Hmmm, Some(FeatureKey::Hmmm))
-        } else if text.starts_with("!np") {
-            (ParsedMessage::Np(text
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect()), Some(FeatureKey::Np))
-        } else if text.starts_with(ctx.safe_word.as_str()) {
-            log::info!("Secret word red");
-            (ParsedMessage::Exit, Some(FeatureKey::Any))
+/// Registry with every built-in command
+pub fn commands(safe_word: String) -> Registry {
+    let mut registry = Registry::new();
+    registry
+        .register(Rice)
+        .register(BugAd)
+        .register(Needle)
+        .register(Ping)
+        .register(Armory)
+        .register(duel::Duel)
+        .register(duel::Accept)
+        .register(trade::Give)
+        .register(trade::Trade)
+        .register(Moon)
+        .register(Tarot)
+        .register(VoidStranger)
+        .register(Mmmm)
+        .register(Hmmm)
+        .register(Np)
+        .register(admin::Bot)
+        .register(SafeWord(safe_word));
+    registry
+}
+
+pub async fn handle(input: Message, ctx: &Context) -> Result<bool, Box<dyn std::error::Error>> {
+    if let Some(event) = Event::parse(&input) {
+        events::handle(event, ctx).await?;
+        return Ok(false);
+    }
+    let message = if let Some(message) = TwitchMessage::parse(&input) {
+        message
+    } else {
+        return Ok(false);
+    };
+    ctx.chatters.seen(&message);
+    let channel = message.channel.as_str();
+    let (command, args) = if let Some(found) = ctx.commands.find(&message.text) {
+        found
+    } else {
+        return Ok(false);
+    };
+    if !ctx.is_enabled(command.feature(), channel) {
+        return Ok(false);
+    }
+    let required = ctx.required_permission(command, channel);
+    if ctx.permission(&message) < required {
+        log::debug!("{}: {} requires {} permission", channel, command.name(), required);
+        return Ok(false);
+    }
+    if let Some(cooldown) = ctx.cooldown(command.name(), channel) {
+        let user_id = message.user_id.as_ref().unwrap_or(&message.login);
+        if let Check::Waiting { remaining, notify } = ctx.cooldowns.check(command.name(), channel, user_id, &cooldown) {
+            log::debug!("{}: {} is on cooldown for {}", channel, command.name(), user_id);
+            if notify {
+                let reply = format!("[💚] {} is resting, wait {} seconds", command.name(), remaining.as_secs() + 1);
+                ctx.reply_or_send(&message, reply.as_str()).await?;
+            }
+            return Ok(false);
+        }
+    }
+    command.execute(ctx, &message, &args).await
+}
+
+/// Command that always answers with the same text
+macro_rules! static_reply {
+    ($type:ident, $name:literal, $feature:expr, $reply:literal) => {
+        struct $type;
+
+        #[async_trait]
+        impl Command for $type {
+            fn name(&self) -> &str {
+                $name
+            }
+
+            fn feature(&self) -> FeatureKey {
+                $feature
+            }
+
+            async fn execute(&self, ctx: &Context, message: &TwitchMessage, _args: &[&str]) -> Result<bool, Box<dyn Error>> {
+                ctx.reply_or_send(message, $reply).await?;
+                Ok(false)
+            }
+        }
+    };
+}
+
+static_reply!(Rice, "!rice", FeatureKey::Rice, "[💚] RICE BURNED TO CHARCOAL!!!");
+static_reply!(BugAd, "!sbob-ad", FeatureKey::BugAd, "[💚] Winter is upon most of the places, but I'm sure you know where the bugs are! Submit yours. Go here -> https://pub.colonq.computer/~nichepenguin/kno/sbob.html");
+static_reply!(VoidStranger, "!voidstranger", FeatureKey::VoidStranger, "[💚] store.steampowered.com/app/2121980");
+
+/// Reacts to any message starting with its name, e.g. "hmmmmm"
+macro_rules! prefix_reply {
+    ($type:ident, $name:literal, $feature:expr, $reply:literal) => {
+        struct $type;
+
+        #[async_trait]
+        impl Command for $type {
+            fn name(&self) -> &str {
+                $name
+            }
+
+            fn feature(&self) -> FeatureKey {
+                $feature
+            }
+
+            fn matches(&self, text: &str) -> bool {
+                text.starts_with($name)
+            }
+
+            async fn execute(&self, ctx: &Context, message: &TwitchMessage, _args: &[&str]) -> Result<bool, Box<dyn Error>> {
+                ctx.reply_with_priority(message, $reply, Priority::Ambient).await?;
+                Ok(false)
+            }
+        }
+    };
+}
+
+prefix_reply!(Mmmm, "mmmm", FeatureKey::Mmmm, "[💚] meisakNoM");
+prefix_reply!(Hmmm, "hmmm", FeatureKey::Hmmm, "[💚] limesHmm");
+
+struct SafeWord(String);
+
+#[async_trait]
+impl Command for SafeWord {
+    fn name(&self) -> &str {
+        self.0.as_str()
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Any
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Owner
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        text.starts_with(self.0.as_str())
+    }
+
+    async fn execute(&self, _ctx: &Context, _message: &TwitchMessage, _args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        log::info!("Secret word red");
+        Ok(true)
+    }
+}
+
+struct Ping;
+
+#[async_trait]
+impl Command for Ping {
+    fn name(&self) -> &str {
+        "!ping"
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Ping
+    }
+
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let reply = if args.is_empty() {
+            "[💚] pong".to_owned()
         } else {
-            (ParsedMessage::Ignore, None)
+            format!("[💚] pong {}", args.join(" "))
         };
-        (parsed, Some(channel.clone()), key)
-    } else {
-        (ParsedMessage::Ignore, None, None)
+        ctx.reply_or_send(message, reply.as_str()).await?;
+        Ok(false)
     }
 }
 
-fn get_message_tag(message: &Message, tag: &str) -> Option<String> {
-    if let Some(tags) = &message.tags {
-        tags.iter().find(|t| t.0 == tag).map(|t| t.1.clone()).flatten()
-    } else {
-        None
+struct Moon;
+
+#[async_trait]
+impl Command for Moon {
+    fn name(&self) -> &str {
+        "!moon"
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Moon
+    }
+
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, _args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let info = ctx.moon.info().await?;
+        let reply = format!(
+            "[💚] [{}] [{} {}] Moon is {}, {} illumination aged {} days, angle {}, distance {} km",
+            info.emoji,
+            info.month,
+            info.day,
+            info.phase,
+            info.illumination.replace('\n', ""),
+            info.age,
+            info.angle,
+            info.distance);
+        log::info!("{}", reply);
+        ctx.reply_or_send(message, reply.as_str()).await?;
+        Ok(false)
     }
 }
 
-pub async fn handle(input: Message, ctx: &Context) -> Result<bool, Box<dyn std::error::Error>> {
-    let (parsed, channel, key) = parse(&input, ctx);
-    if let ParsedMessage::Ignore = parsed  {
-        return Ok(false);
+struct Needle;
+
+#[async_trait]
+impl Command for Needle {
+    fn name(&self) -> &str {
+        "!needle"
     }
-    if channel.is_none() || key.is_none() || !ctx.is_enabled(key.unwrap(), &channel.as_ref().unwrap()[..])
-    {
-        return Ok(false);
+
+    fn aliases(&self) -> &[&str] {
+        &["!haystack"]
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Needle
+    }
+
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, _args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let rand = rand::rng().random::<u8>();
+        if  rand > 250 {
+            let username = message.display_name.clone();
+            let needle = ctx.swords.draw(&username, true).await.map_err(|e| e.to_string())?;
+            ctx.reply_or_send(message, format!("[💚] You rummage around in a haystack... finding {}!", needle).as_str()).await?;
+            log::info!("{}: {} found {}", message.channel, username, &needle);
+            ctx.swords.log(needle, Arc::clone(&ctx.armory)).await;
+        } else if rand == 16 {
+            ctx.reply_or_send(message, "[💚] You wummage awound in a haystawk... not windink any needuws... uwu...").await?
+        } else {
+            ctx.reply_or_send(message, "[💚] You rummage around in a haystack... not finding any needles...").await?
+        }
+        Ok(false)
+    }
+}
+
+struct Armory;
+
+#[async_trait]
+impl Command for Armory {
+    fn name(&self) -> &str {
+        "!armory"
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Tarot
     }
-    let channel = channel.unwrap();
-    match parsed {
-        ParsedMessage::BugAd => ctx.reply_or_send(input, "[💚] Winter is upon most of the places, but I'm sure you know where the bugs are! Submit yours. Go here -> https://pub.colonq.computer/~nichepenguin/kno/sbob.html").await?,
-        ParsedMessage::Exit => {
-            let username = get_message_tag(&input, "display-name").unwrap_or("unknown".to_owned());
-            if username == "nichePenguin" {
-                return Ok(true);
+
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let id = args.first()
+            .map(|s| s.replace('#', "").parse::<i64>().ok())
+            .flatten();
+        let answer = match args {
+            ["top"] => Some(armory_top(ctx).await),
+            ["stats"] => Some(armory_stats(ctx, &message.display_name).await),
+            ["stats", user] => Some(armory_stats(ctx, user.trim_start_matches('@')).await),
+            ["global"] => Some(armory_global(ctx).await),
+            [_, ..] if id.is_none() => Some(armory_search(ctx, args).await),
+            _ => None
+        };
+        if let Some(reply) = answer {
+            log::info!("{}: {}", message.channel, reply);
+            ctx.reply_or_send(message, reply.as_str()).await?;
+            return Ok(false);
+        }
+        let username = message.display_name.clone();
+        let (count, example) = ctx.swords.check(&username, id).await;
+        let reply = if let Some(example) = example {
+            let label = if let Some(id) = example.id {
+                format!(" (#{})", id)
             } else {
-                return Ok(false);
-            }
-        },
-        ParsedMessage::Ignore => {},
-        ParsedMessage::Moon => {
-            let info = ctx.moon.info().await?;
-            let reply = format!(
-                "[💚] [{}] [{} {}] Moon is {}, {} illumination aged {} days, angle {}, distance {} km",
-                info.emoji,
-                info.month,
-                info.day,
-                info.phase,
-                info.illumination.replace('\n', ""),
-                info.age,
-                info.angle,
-                info.distance);
-            log::info!("{}", reply);
-            ctx.reply_or_send(input, reply.as_str()).await?
-        },
-        ParsedMessage::Needle => {
-            let rand = rand::rng().random::<u8>();
-            if  rand > 250 {
-                let username = get_message_tag(&input, "display-name").unwrap_or("unknown".to_owned());
-                let needle = ctx.swords.draw(&username, true).await.map_err(|e| e.to_string())?;
-                ctx.reply_or_send(input, format!("[💚] You rummage around in a haystack... finding {}!", needle).as_str()).await?;
-                log::info!("{}: {} found {}", channel, username, &needle);
-                ctx.swords.log(needle, Arc::clone(&ctx.gateway)).await;
-            } else if rand == 16 {
-                ctx.reply_or_send(input, "[💚] You wummage awound in a haystawk... not windink any needuws... uwu...").await?
+                String::new()
+            };
+            if let Some(_) = id {
+                let provenance = example.provenance().map(|p| format!(" {}.", p)).unwrap_or_default();
+                format!("[💚] You peer into the unknown, and your psyche reaches {}'s blade: {}.{}", example.owner, example, provenance)
+            } else if count == 1 {
+                format!("[💚] A single blade is kept safe in your armory: {}.{}", example, label)
+            } else if count < 100 {
+                format!("[💚] Your armory boasts {} swords, including such specimen as {}.{}", count, example, label)
             } else {
-                ctx.reply_or_send(input, "[💚] You rummage around in a haystack... not finding any needles...").await?
+                format!("[💚] Your armory groans beneath the  weight of {} blades, yet you regard just one this time: {}.{}", count, example, label)
             }
-        },
-        ParsedMessage::Ping(text) => {
-            let reply = format!("[💚] pong{}", &text[5..]);
-            ctx.reply_or_send(input, reply.as_str()).await?
-        },
-        ParsedMessage::VoidStranger => ctx.reply_or_send(input, "[💚] store.steampowered.com/app/2121980").await?,
-        ParsedMessage::Rice => ctx.reply_or_send(input, "[💚] RICE BURNED TO CHARCOAL!!!").await?,
-        ParsedMessage::Mmmm => ctx.reply_or_send(input, "[💚] meisakNoM").await?,
-        ParsedMessage::Hmmm => ctx.reply_or_send(input, "[💚] limesHmm").await?,
-        ParsedMessage::Armory(id) => {
-            let username = get_message_tag(&input, "display-name").unwrap_or("unknown".to_owned());
-            let (count, example) = ctx.swords.check(&username, id).await;
-            let message = if let Some(example) = example {
-                let label = if let Some(id) = example.id {
-                    format!(" (#{})", id)
-                } else {
-                    String::new()
-                };
-                if let Some(_) = id {
-                    format!("[💚] You peer into the unknown, and your psyche reaches {}'s blade: {}.", example.owner, example)
-                } else if count == 1 {
-                    format!("[💚] A single blade is kept safe in your armory: {}.{}", example, label)
-                } else if count < 100 {
-                    format!("[💚] Your armory boasts {} swords, including such specimen as {}.{}", count, example, label)
-                } else {
-                    format!("[💚] Your armory groans beneath the  weight of {} blades, yet you regard just one this time: {}.{}", count, example, label)
-                }
-            } else if let Some(_) = id {
-                format!("[💚] Your peer into the unknown, but the blade you think of eludes you.")
-            } else {
-                format!("[💚] Your hand has not yet taken to your sword...")
-            };
-            log::info!("{}: {}", channel, message);
-            ctx.reply_or_send(input, message.as_str()).await?;
+        } else if let Some(_) = id {
+            format!("[💚] Your peer into the unknown, but the blade you think of eludes you.")
+        } else {
+            format!("[💚] Your hand has not yet taken to your sword...")
+        };
+        log::info!("{}: {}", message.channel, reply);
+        ctx.reply_or_send(message, reply.as_str()).await?;
+        Ok(false)
+    }
+}
+
+const LEADERBOARD_SIZE: usize = 3;
+
+fn ranking(entries: &[(String, usize)]) -> String {
+    if entries.is_empty() {
+        return "nobody yet".to_owned();
+    }
+    entries.iter().map(|(name, count)| format!("{} ({})", name, count)).collect::<Vec<_>>().join(", ")
+}
+
+async fn armory_top(ctx: &Context) -> String {
+    let top = ctx.swords.leaderboard(LEADERBOARD_SIZE).await;
+    format!("[💚] Most blades: {}. Most artifacts: {}. Rarest materials: {}.",
+        ranking(&top.most_blades), ranking(&top.most_artifacts), ranking(&top.rarest_materials))
+}
+
+async fn armory_stats(ctx: &Context, user: &str) -> String {
+    let Some(collection) = ctx.swords.collection(user).await else {
+        return format!("[💚] {} has not yet taken to the sword...", user);
+    };
+    let qualities = collection.quality_counts().iter()
+        .map(|(quality, count)| format!("{} {}", count, quality))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let favourite = |counts: &std::collections::HashMap<String, usize>| counts.iter()
+        .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)))
+        .map(|(name, count)| format!("{} ({})", name, count))
+        .unwrap_or_default();
+    format!("[💚] {} holds {} blades: {}. Mostly {} and {}, {} kinds of material, {} needles found in haystacks.",
+        collection.owner, collection.total, qualities,
+        favourite(&collection.materials), favourite(&collection.types),
+        collection.materials.len(), collection.needles)
+}
+
+/// `!armory adamantine katana`, `!armory @someone masterwork`, see `armory::Query`
+async fn armory_search(ctx: &Context, args: &[&str]) -> String {
+    let found = ctx.swords.search(&Query::parse(args)).await;
+    let Some(sample) = found.sample else {
+        return format!("[💚] No blade in the armory answers to \"{}\".", args.join(" "));
+    };
+    let label = sample.id.map(|id| format!(" (#{})", id)).unwrap_or_default();
+    if found.count == 1 {
+        format!("[💚] A single blade answers: {}'s {}.{}", sample.owner, sample, label)
+    } else {
+        format!("[💚] {} blades held by {} chatters answer, such as {}'s {}.{}",
+            found.count, found.owners, sample.owner, sample, label)
+    }
+}
+
+async fn armory_global(ctx: &Context) -> String {
+    let stats = ctx.swords.global_stats().await;
+    let combination = |c: Option<(String, usize)>| c.map(|(name, count)| format!("{} ({})", name, count))
+        .unwrap_or("none".to_owned());
+    format!("[💚] The armory keeps {} blades of {} chatters, {} of them artifacts. Rarest combination seen: {}, most common: {}.",
+        stats.total, stats.owners, stats.artifacts,
+        combination(stats.rarest_combination), combination(stats.commonest_combination))
+}
+
+struct Tarot;
+
+#[async_trait]
+impl Command for Tarot {
+    fn name(&self) -> &str {
+        "!draw"
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Tarot
+    }
+
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, _args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let channel = message.channel.as_str();
+        let username = message.display_name.clone();
+        if rand::rng().random::<u8>() >= (255 - 32) {
+            let sword = ctx.swords.draw(&username, false).await.map_err(|e| e.to_string())?;
+            let reply = format!("[💚] {} drew a sword, en garde! It's {}.", username, sword);
+            log::info!("{}: {}", channel, reply);
+            ctx.reply_or_send(message, reply.as_str()).await?;
+            ctx.swords.log(sword, Arc::clone(&ctx.armory)).await;
             return Ok(false);
-        },
-        ParsedMessage::Tarot => {
-            let username = get_message_tag(&input, "display-name").unwrap_or("unknown".to_owned());
-            if rand::rng().random::<u8>() >= (255 - 32) {
-                let sword = ctx.swords.draw(&username, false).await.map_err(|e| e.to_string())?;
-                let message = format!("[💚] {} drew a sword, en garde! It's {}.", username, sword);
-                log::info!("{}: {}", channel, message);
-                ctx.reply_or_send(input, message.as_str()).await?;
-                ctx.swords.log(sword, Arc::clone(&ctx.gateway)).await;
-                return Ok(false);
-            }
-            let card = ctx.tarot.draw();
-            if let Err(e) = card {
-                log::error!("Error drawing a card for {}: {}", input.source_nickname().unwrap_or("unknown"), e);
-                return Err(e);
-            }
-            let (card, affinity) = card.map_err(|e| format!("Error drawing card: {}", e))?;
-            let color = get_message_tag(&input, "color").unwrap_or("#FFFFFF".to_owned());
-            let user_id = get_message_tag(&input, "user-id").unwrap_or("unknown".to_owned());
-            if let Err(e) = log_card(
-                &ctx.tarot_history,
-                &card, affinity, &channel, &username, &color, &user_id) {
-                log::error!("Error logging card draw by {} : {}", username, e);
-            }
-            log::info!("{}: {} drew {}", channel, username, card);
-            let sigil = if card.contains("Reversed") {"[💜]"} else {"[💚]"};
-            let reply = format!("{} {}", sigil, card);
-            ctx.reply_or_send(input, reply.as_str()).await?
-        },
-        ParsedMessage::Np(tokens) => {
-            let username = get_message_tag(&input, "display-name").unwrap_or("unknown".to_owned());
-            log::info!("Noted user: {}", username);
-            log::debug!("User sent: {:?}", tokens);
-            if !std::fs::read_to_string(&ctx.noted_users)?.split_whitespace().any(|s| s == username){
-                np_utils::log_line(&ctx.noted_users, username, 1000)?;
-            }
-            ctx.reply_or_send(input, "Your curiosity will be rewarded").await?
         }
+        let card = ctx.tarot.draw();
+        if let Err(e) = card {
+            log::error!("Error drawing a card for {}: {}", message.login, e);
+            return Err(e);
+        }
+        let (card, affinity) = card.map_err(|e| format!("Error drawing card: {}", e))?;
+        let color = message.color.as_deref().unwrap_or("#FFFFFF");
+        let user_id = message.user_id.as_deref().unwrap_or("unknown");
+        if let Err(e) = log_card(
+            &ctx.tarot_history,
+            &card, affinity, channel, &username, color, user_id) {
+            log::error!("Error logging card draw by {} : {}", username, e);
+        }
+        log::info!("{}: {} drew {}", channel, username, card);
+        let sigil = if card.contains("Reversed") {"[💜]"} else {"[💚]"};
+        let reply = format!("{} {}", sigil, card);
+        ctx.reply_or_send(message, reply.as_str()).await?;
+        Ok(false)
+    }
+}
+
+struct Np;
+
+#[async_trait]
+impl Command for Np {
+    fn name(&self) -> &str {
+        "!np"
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Np
+    }
+
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let username = message.display_name.clone();
+        log::info!("Noted user: {}", username);
+        log::debug!("User sent: {:?}", args);
+        if !std::fs::read_to_string(&ctx.noted_users)?.split_whitespace().any(|s| s == username){
+            np_utils::log_line(&ctx.noted_users, username, 1000)?;
+        }
+        ctx.reply_or_send(message, "Your curiosity will be rewarded").await?;
+        Ok(false)
     }
-    return Ok(false);
 }
 
 fn log_card(
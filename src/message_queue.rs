@@ -1,31 +1,421 @@
 use irc::client::prelude::{Client, Command, Message};
+
+use crate::config::Connection;
 use tokio::{
-    sync::{mpsc, Mutex},
-    time::{Instant, Duration, sleep},
+    sync::Notify,
+    time::{Instant, Duration, sleep, sleep_until},
     task::JoinHandle
 };
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex}
+};
+
+/// Twitch rejects chat messages longer than this, counted in characters
+pub const MESSAGE_LIMIT: usize = 500;
+
+/// Twitch chat limits: messages per window in a channel, more where the bot is a moderator
+const RATE_WINDOW: Duration = Duration::from_secs(30);
+const RATE_LIMIT: usize = 20;
+const RATE_LIMIT_MODERATOR: usize = 100;
+const MAX_QUEUED: usize = 100;
+const SLOW_WAIT: Duration = Duration::from_secs(5);
+/// Twitch drops a message identical to the previous one in the channel within this window
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(30);
+/// Invisible tag character, enough for Twitch to consider the message different
+const DUPLICATE_SUFFIX: &str = " \u{E0000}";
+/// A NOTICE this long after sending can no longer be pinned on the message
+const FEEDBACK_WINDOW: Duration = Duration::from_secs(10);
+/// Rejected messages are retried after 2, 4, ... seconds
+const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_RETRIES: u32 = 2;
+/// Pause for a timeout whose length the NOTICE didn't mention
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(600);
+const DRAIN_POLL: Duration = Duration::from_millis(50);
+
+/// Lanes are drained in order, a queued system message always goes before any reply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Admin and moderation related messages
+    System,
+    /// Answers to commands
+    Reply,
+    /// Reactions nobody asked for, e.g. "hmmm"
+    Ambient,
+}
+
+impl Priority {
+    /// How long a message may wait before it is no longer worth sending
+    fn max_age(self, max_age: Duration) -> Option<Duration> {
+        match self {
+            _ if max_age.is_zero() => None,
+            Priority::System => None,
+            Priority::Reply => Some(max_age),
+            Priority::Ambient => Some(max_age / 4),
+        }
+    }
+}
+
+const LANES: usize = 3;
+
+struct Queued {
+    message: Message,
+    channel: Option<String>,
+    priority: Priority,
+    enqueued: Instant,
+    expires: Option<Instant>,
+    /// Set when retrying a rejected message
+    not_before: Option<Instant>,
+    attempts: u32
+}
+
+/// Message waiting for Twitch to confirm it with a USERSTATE or reject it with a NOTICE
+struct Unconfirmed {
+    message: Message,
+    priority: Priority,
+    attempts: u32,
+    sent: Instant
+}
+
+impl Queued {
+    fn text(&self) -> Option<&str> {
+        match &self.message.command {
+            Command::PRIVMSG(_, text) => Some(text),
+            _ => None
+        }
+    }
+
+    fn reply_parent(&self) -> Option<String> {
+        self.message.tags.iter().flatten()
+            .find(|t| t.0 == "reply-parent-msg-id")
+            .and_then(|t| t.1.clone())
+    }
+}
+
+/// Messages sent per channel within the last rate window
+#[derive(Default)]
+struct Buckets {
+    sent: HashMap<String, VecDeque<Instant>>,
+    moderator: HashSet<String>
+}
+
+impl Buckets {
+    /// None if the channel can take a message now, otherwise when it can
+    fn available_at(&mut self, channel: &str, now: Instant) -> Option<Instant> {
+        let limit = if self.moderator.contains(channel) {
+            RATE_LIMIT_MODERATOR
+        } else {
+            RATE_LIMIT
+        };
+        let sent = self.sent.entry(channel.to_owned()).or_default();
+        while sent.front().is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW) {
+            sent.pop_front();
+        }
+        if sent.len() < limit {
+            None
+        } else {
+            sent.front().map(|t| *t + RATE_WINDOW)
+        }
+    }
+
+    fn record(&mut self, channel: &str, now: Instant) {
+        self.sent.entry(channel.to_owned()).or_default().push_back(now);
+    }
+}
+
+enum Next {
+    Message(Queued),
+    WaitUntil(Instant),
+    Empty
+}
+
+struct State {
+    lanes: [VecDeque<Queued>; LANES],
+    buckets: Buckets,
+    last_message: Instant,
+    /// Last text sent to each channel, when, and the message it replied to
+    last_sent: HashMap<String, (String, Instant, Option<String>)>,
+    unconfirmed: HashMap<String, Unconfirmed>,
+    /// A message was taken off the lanes and is about to be sent
+    in_flight: bool,
+    /// Channels the bot can't talk in, until a time or for good when banned
+    paused: HashMap<String, Option<Instant>>,
+    /// Rejections by NOTICE msg-id
+    failures: HashMap<String, u64>,
+    max_age: Duration,
+    duplicate_suffix: bool,
+    sent: u64,
+    dropped: u64,
+    total_wait: Duration
+}
+
+impl State {
+    fn new(connection: &Connection) -> Self {
+        Self {
+            lanes: Default::default(),
+            buckets: Buckets::default(),
+            last_message: Instant::now(),
+            last_sent: HashMap::new(),
+            unconfirmed: HashMap::new(),
+            in_flight: false,
+            paused: HashMap::new(),
+            failures: HashMap::new(),
+            max_age: Duration::from_millis(connection.max_age_ms),
+            duplicate_suffix: connection.duplicate_suffix,
+            sent: 0,
+            dropped: 0,
+            total_wait: Duration::ZERO
+        }
+    }
+
+    /// Queue a message unless an identical one is already waiting for the same channel,
+    /// replying to the same message if it is a reply
+    fn push(&mut self, message: Message, priority: Priority, now: Instant) {
+        if self.lanes.iter().map(VecDeque::len).sum::<usize>() >= MAX_QUEUED {
+            log::error!("Message queue is full, dropping {:?} message", priority);
+            self.dropped += 1;
+            return;
+        }
+        let channel = if let Command::PRIVMSG(channel, _) = &message.command {
+            Some(channel.clone())
+        } else {
+            None
+        };
+        if let Some(channel) = channel.as_ref().filter(|c| self.paused.get(*c).is_some_and(Option::is_none)) {
+            log::debug!("Not queueing a message to {}, the bot is banned there", channel);
+            self.dropped += 1;
+            return;
+        }
+        let queued = Queued {
+            message,
+            channel,
+            priority,
+            enqueued: now,
+            expires: priority.max_age(self.max_age).map(|age| now + age),
+            not_before: None,
+            attempts: 0
+        };
+        if queued.text().is_some() && self.lanes.iter().flatten()
+            .any(|q| q.channel == queued.channel && q.text() == queued.text() && q.reply_parent() == queued.reply_parent())
+        {
+            log::debug!("Collapsing duplicate message to {}", queued.channel.as_deref().unwrap_or("server"));
+            self.dropped += 1;
+            return;
+        }
+        self.lanes[priority as usize].push_back(queued);
+    }
+
+    /// Forget messages that waited past their max age
+    fn drop_stale(&mut self, now: Instant) {
+        let mut dropped = 0;
+        for lane in self.lanes.iter_mut() {
+            lane.retain(|queued| {
+                let stale = queued.expires.is_some_and(|expires| expires <= now);
+                if stale {
+                    log::warn!("Dropping message to {} after {}ms in the queue",
+                        queued.channel.as_deref().unwrap_or("server"), (now - queued.enqueued).as_millis());
+                    dropped += 1;
+                }
+                !stale
+            });
+        }
+        self.dropped += dropped;
+        self.paused.retain(|_, until| !matches!(until, Some(until) if *until <= now));
+    }
+
+    /// Message went out, keep it around in case Twitch rejects it
+    fn sent(&mut self, queued: Queued, now: Instant) {
+        if let Some(channel) = queued.channel {
+            self.unconfirmed.insert(channel, Unconfirmed {
+                message: queued.message,
+                priority: queued.priority,
+                attempts: queued.attempts,
+                sent: now
+            });
+        }
+    }
+
+    /// Twitch rejected the last message sent to the channel, `msg_id` is the NOTICE reason
+    fn rejected(&mut self, channel: &str, msg_id: &str, notice: &str, now: Instant) {
+        let reason = match msg_id {
+            "msg_ratelimit" | "msg_duplicate" | "msg_banned" | "msg_timedout" => msg_id,
+            _ => {
+                log::debug!("{}: NOTICE {}: {}", channel, msg_id, notice);
+                return;
+            }
+        };
+        log::warn!("{}: message rejected with {}: {}", channel, reason, notice);
+        *self.failures.entry(reason.to_owned()).or_default() += 1;
+        let rejected = self.unconfirmed.remove(channel)
+            .filter(|u| now.duration_since(u.sent) < FEEDBACK_WINDOW);
+
+        match reason {
+            "msg_banned" => self.pause(channel, None),
+            "msg_timedout" => {
+                let seconds = notice.split_whitespace().find_map(|w| w.parse().ok());
+                let timeout = seconds.map(Duration::from_secs).unwrap_or(DEFAULT_TIMEOUT);
+                self.pause(channel, Some(now + timeout));
+            },
+            _ => {}
+        }
+
+        let retry = reason == "msg_ratelimit" || (reason == "msg_duplicate" && self.duplicate_suffix);
+        match rejected {
+            Some(rejected) if retry && rejected.attempts < MAX_RETRIES => {
+                let backoff = RETRY_BACKOFF * 2u32.pow(rejected.attempts);
+                log::info!("{}: retrying in {}s", channel, backoff.as_secs());
+                self.lanes[rejected.priority as usize].push_front(Queued {
+                    message: rejected.message,
+                    channel: Some(channel.to_owned()),
+                    priority: rejected.priority,
+                    enqueued: now,
+                    expires: rejected.priority.max_age(self.max_age).map(|age| now + backoff + age),
+                    not_before: Some(now + backoff),
+                    attempts: rejected.attempts + 1
+                });
+            },
+            Some(_) => self.dropped += 1,
+            None => log::debug!("{}: no recent message to pin the rejection on", channel)
+        }
+    }
+
+    /// Hold messages to a channel until a time, or drop them for good
+    fn pause(&mut self, channel: &str, until: Option<Instant>) {
+        log::warn!("Pausing messages to {}{}", channel, if until.is_none() { " indefinitely" } else { "" });
+        self.paused.insert(channel.to_owned(), until);
+        if until.is_none() {
+            for lane in self.lanes.iter_mut() {
+                let before = lane.len();
+                lane.retain(|queued| queued.channel.as_deref() != Some(channel));
+                self.dropped += (before - lane.len()) as u64;
+            }
+        }
+    }
+
+    /// Make sure Twitch won't reject the message as a repeat of the previous one,
+    /// false if it has to be dropped. The same text answering a different message is a reply
+    /// in its own right and always gets the suffix, other repeats only when configured
+    fn deduplicate(&mut self, queued: &mut Queued, now: Instant) -> bool {
+        let parent = queued.reply_parent();
+        let (channel, text) = match (&queued.channel, &mut queued.message.command) {
+            (Some(channel), Command::PRIVMSG(_, text)) => (channel, text),
+            _ => return true
+        };
+        let repeated = self.last_sent.get(channel)
+            .filter(|(last, at, _)| last == text && now.duration_since(*at) < DUPLICATE_WINDOW)
+            .map(|(_, _, last_parent)| *last_parent == parent);
+        if let Some(same_parent) = repeated {
+            let fits = text.chars().count() + DUPLICATE_SUFFIX.chars().count() <= MESSAGE_LIMIT;
+            if !fits || (same_parent && !self.duplicate_suffix) {
+                log::debug!("Dropping repeated message to {}", channel);
+                self.dropped += 1;
+                return false;
+            }
+            text.push_str(DUPLICATE_SUFFIX);
+        }
+        self.last_sent.insert(channel.clone(), (text.clone(), now, parent));
+        true
+    }
+
+    /// Highest priority message whose channel is not rate limited
+    fn pop_ready(&mut self, now: Instant) -> Next {
+        self.drop_stale(now);
+        let State { lanes, buckets, paused, .. } = self;
+        let mut earliest: Option<Instant> = None;
+        for lane in lanes.iter_mut() {
+            let mut ready = None;
+            for (index, queued) in lane.iter().enumerate() {
+                let channel = queued.channel.as_deref();
+                let available_at = [
+                    queued.not_before,
+                    channel.and_then(|c| paused.get(c).copied().flatten()),
+                    channel.and_then(|c| buckets.available_at(c, now))
+                ].into_iter().flatten().filter(|at| *at > now).max();
+                match available_at {
+                    None => {
+                        ready = Some(index);
+                        break;
+                    },
+                    Some(at) => earliest = Some(earliest.map_or(at, |e| e.min(at)))
+                }
+            }
+            if let Some(queued) = ready.and_then(|index| lane.remove(index)) {
+                return Next::Message(queued);
+            }
+        }
+        match earliest {
+            Some(at) => Next::WaitUntil(at),
+            None => Next::Empty
+        }
+    }
+}
+
+/// Snapshot of the queue for monitoring
+#[derive(Debug, Clone)]
+pub struct QueueStats {
+    /// Messages waiting, per priority lane
+    pub queued: [usize; LANES],
+    /// How long the oldest waiting message has been queued
+    pub oldest: Duration,
+    /// Average time between queueing and sending
+    pub average_wait: Duration,
+    pub sent: u64,
+    /// Stale, collapsed and repeated messages that were never sent
+    pub dropped: u64,
+    /// Messages Twitch rejected, by NOTICE msg-id
+    pub failures: HashMap<String, u64>,
+    /// Channels the bot is banned or timed out in
+    pub paused: Vec<String>
+}
 
 pub struct MessageQueue {
-    sender: mpsc::Sender<Message>,
-    last_message: Arc<Mutex<Instant>>,
+    state: Arc<Mutex<State>>,
+    notify: Arc<Notify>,
     send_loop: JoinHandle<()>,
 }
 
-pub async fn start(client: Arc<std::sync::Mutex<Client>>, delay_ms: u64) -> MessageQueue {
-    let (tx, mut rx) = mpsc::channel::<Message>(100);
-    let last_message = Arc::new(Mutex::new(Instant::now()));
-    let last_message_ref = Arc::clone(&last_message);
+pub async fn start(client: Arc<Mutex<Client>>, connection: &Connection) -> MessageQueue {
+    let delay_ms = connection.queue_delay_ms;
+    let state = Arc::new(Mutex::new(State::new(connection)));
+    let notify = Arc::new(Notify::new());
+    let state_ref = Arc::clone(&state);
+    let notify_ref = Arc::clone(&notify);
     let handle = tokio::task::spawn(async move {
         let delay = Duration::from_millis(delay_ms);
         log::info!("Starting message queue");
         loop {
-            let message = rx.recv().await.expect("Channel to message queue closed");
+            let next = {
+                let mut state = lock(&state);
+                let next = state.pop_ready(Instant::now());
+                state.in_flight = matches!(next, Next::Message(_));
+                next
+            };
+            let mut queued = match next {
+                Next::Message(queued) => queued,
+                Next::WaitUntil(at) => {
+                    tokio::select! {
+                        _ = sleep_until(at) => {},
+                        _ = notify.notified() => {}
+                    }
+                    continue;
+                },
+                Next::Empty => {
+                    notify.notified().await;
+                    continue;
+                }
+            };
 
-            let mut passed = Instant::now() - get_last_message(&last_message).await;
-            while passed <= delay {
-                sleep(delay).await;
-                passed = Instant::now() - get_last_message(&last_message).await;
+            loop {
+                let passed = Instant::now() - lock(&state).last_message;
+                if passed > delay {
+                    break;
+                }
+                sleep(delay - passed).await;
+            }
+
+            if !lock(&state).deduplicate(&mut queued, Instant::now()) {
+                lock(&state).in_flight = false;
+                continue;
             }
 
             {
@@ -34,8 +424,8 @@ pub async fn start(client: Arc<std::sync::Mutex<Client>>, delay_ms: u64) -> Mess
                     log::error!("Error sending message due to obtaining mutex: {}", e);
                 }
                 let client = client.unwrap();
-                if let Err(e) = client.send(message.clone()) {
-                    match message.command {
+                if let Err(e) = client.send(queued.message.clone()) {
+                    match &queued.message.command {
                         Command::PRIVMSG(channel, chat_message) => 
                             log::error!("Error sending message \" {}\" to {}: {}",
                                 chat_message, channel, e),
@@ -43,34 +433,356 @@ pub async fn start(client: Arc<std::sync::Mutex<Client>>, delay_ms: u64) -> Mess
                     }
                 }
             }
-            *last_message.lock().await = Instant::now();
+
+            let now = Instant::now();
+            let waited = now - queued.enqueued;
+            if waited > SLOW_WAIT {
+                log::warn!("Message to {} waited {}ms in the queue",
+                    queued.channel.as_deref().unwrap_or("server"), waited.as_millis());
+            }
+            let mut locked = lock(&state);
+            locked.last_message = now;
+            locked.sent += 1;
+            locked.total_wait += waited;
+            if let Some(channel) = &queued.channel {
+                locked.buckets.record(channel, now);
+            }
+            locked.sent(queued, now);
+            locked.in_flight = false;
         }
     });
 
     MessageQueue {
-        sender: tx,
-        last_message: last_message_ref,
+        state: state_ref,
+        notify: notify_ref,
         send_loop: handle
     }
 }
 
-async fn get_last_message(last_message: &Arc<Mutex<Instant>>) -> Instant {
-    *last_message.lock().await
+fn lock(state: &Mutex<State>) -> std::sync::MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| {
+        log::error!("Message queue lock poisoned, recovering: {}", e);
+        e.into_inner()
+    })
 }
 
 impl MessageQueue {
-    pub async fn send(&self, message: Message) {
-        if let Err(e) = self.sender.send(message).await {
-            log::error!("Error sending message: {}", e);
+    /// Queue a message, splitting it into numbered chunks when it is over the limit
+    pub async fn send_with_priority(&self, message: Message, priority: Priority) {
+        {
+            let mut state = lock(&self.state);
+            let now = Instant::now();
+            for message in split_message(message) {
+                state.push(message, priority, now);
+            }
         }
+        self.notify.notify_one();
     }
 
     pub async fn reset_delay(&self) {
-        let mut last_message = self.last_message.lock().await;
-        *last_message = Instant::now();
+        lock(&self.state).last_message = Instant::now();
+    }
+
+    /// Twitch allows more messages in channels where the bot is a moderator, as reported by USERSTATE
+    pub fn set_moderator(&self, channel: &str, moderator: bool) {
+        let mut state = lock(&self.state);
+        if moderator {
+            if state.buckets.moderator.insert(channel.to_owned()) {
+                log::info!("Moderator in {}, raising rate limit", channel);
+            }
+        } else {
+            state.buckets.moderator.remove(channel);
+        }
+    }
+
+    /// Messages not sent yet
+    pub fn pending(&self) -> usize {
+        let state = lock(&self.state);
+        state.lanes.iter().map(VecDeque::len).sum::<usize>() + state.in_flight as usize
+    }
+
+    /// Wait until everything queued is sent, false if that took longer than `timeout`
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.pending() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            sleep(DRAIN_POLL).await;
+        }
+        true
+    }
+
+    /// Twitch acknowledged the last message with a USERSTATE
+    pub fn confirm(&self, channel: &str) {
+        lock(&self.state).unconfirmed.remove(channel);
+    }
+
+    /// NOTICE with a msg-id, the last message to the channel may have been rejected
+    pub fn rejected(&self, channel: &str, msg_id: &str, notice: &str) {
+        lock(&self.state).rejected(channel, msg_id, notice, Instant::now());
+        self.notify.notify_one();
+    }
+
+    /// Start talking in a channel again, e.g. after being unbanned
+    pub fn resume(&self, channel: &str) -> bool {
+        lock(&self.state).paused.remove(channel).is_some()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let state = lock(&self.state);
+        let now = Instant::now();
+        let mut queued = [0; LANES];
+        for (count, lane) in queued.iter_mut().zip(state.lanes.iter()) {
+            *count = lane.len();
+        }
+        QueueStats {
+            queued,
+            oldest: state.lanes.iter()
+                .flatten()
+                .map(|q| now - q.enqueued)
+                .max()
+                .unwrap_or_default(),
+            average_wait: if state.sent == 0 {
+                Duration::ZERO
+            } else {
+                state.total_wait / state.sent as u32
+            },
+            sent: state.sent,
+            dropped: state.dropped,
+            failures: state.failures.clone(),
+            paused: state.paused.keys().cloned().collect()
+        }
     }
 
     pub fn stop_loop(&self) {
         self.send_loop.abort();
     }
 }
+
+/// Split a PRIVMSG over the limit into numbered chunks, tags such as the reply parent stay on the first one
+fn split_message(message: Message) -> Vec<Message> {
+    let (channel, chunks) = match &message.command {
+        Command::PRIVMSG(channel, text) if text.chars().count() > MESSAGE_LIMIT =>
+            (channel.clone(), split_numbered(text)),
+        _ => return vec![message]
+    };
+    let total = chunks.len();
+    chunks.into_iter().enumerate().map(|(i, chunk)| Message {
+        tags: if i == 0 { message.tags.clone() } else { None },
+        prefix: None,
+        command: Command::PRIVMSG(channel.clone(), format!("{} ({}/{})", chunk, i + 1, total))
+    }).collect()
+}
+
+/// Split leaving room in every chunk for its " (i/n)" counter. More chunks can need a wider
+/// counter, so the split is redone until the counter for the last chunk fits
+fn split_numbered(text: &str) -> Vec<String> {
+    let counter = |total: usize| format!(" ({0}/{0})", total).chars().count();
+    let mut reserve = counter(text.chars().count().div_ceil(MESSAGE_LIMIT));
+    loop {
+        let chunks = split_text(text, MESSAGE_LIMIT - reserve);
+        if counter(chunks.len()) <= reserve {
+            return chunks;
+        }
+        reserve = counter(chunks.len());
+    }
+}
+
+/// Split on whitespace into chunks of at most `limit` characters,
+/// words longer than that are cut on character boundaries
+fn split_text(text: &str, limit: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for mut word in text.split_whitespace() {
+        let mut word_len = word.chars().count();
+        while word_len > limit {
+            if current_len > 0 {
+                chunks.push(std::mem::take(&mut current));
+                current_len = 0;
+            }
+            let (index, _) = word.char_indices().nth(limit).expect("Word is longer than the limit");
+            chunks.push(word[..index].to_owned());
+            word = &word[index..];
+            word_len -= limit;
+        }
+        if current_len > 0 && current_len + 1 + word_len > limit {
+            chunks.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        if current_len > 0 {
+            current.push(' ');
+            current_len += 1;
+        }
+        current.push_str(word);
+        current_len += word_len;
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use irc::proto::message::Tag;
+
+    #[test]
+    fn keeps_short_messages() {
+        let message: Message = Command::PRIVMSG("#chan".to_owned(), "[💚] short".to_owned()).into();
+        assert_eq!(split_message(message.clone()), vec![message]);
+    }
+
+    #[test]
+    fn splits_on_words_and_keeps_reply_tag_on_first_chunk() {
+        let text = "💚 sword ".repeat(100);
+        let message = Message {
+            tags: Some(vec![Tag("reply-parent-msg-id".to_owned(), Some("abc".to_owned()))]),
+            prefix: None,
+            command: Command::PRIVMSG("#chan".to_owned(), text.clone())
+        };
+        let chunks = split_message(message);
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[0].tags.is_some());
+        assert!(chunks[1].tags.is_none());
+
+        let mut joined = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let Command::PRIVMSG(_, chunk) = &chunk.command else { panic!("Not a PRIVMSG") };
+            assert!(chunk.chars().count() <= MESSAGE_LIMIT);
+            let suffix = format!(" ({}/2)", i + 1);
+            joined.push(chunk.strip_suffix(&suffix).expect("Chunk is not numbered").to_owned());
+        }
+        assert_eq!(joined.join(" "), text.trim_end());
+    }
+
+    fn privmsg(channel: &str, text: &str) -> Message {
+        Command::PRIVMSG(channel.to_owned(), text.to_owned()).into()
+    }
+
+    fn drain(state: &mut State, now: Instant) -> Vec<String> {
+        let mut texts = Vec::new();
+        while let Next::Message(mut queued) = state.pop_ready(now) {
+            if state.deduplicate(&mut queued, now) {
+                texts.push(queued.text().unwrap_or_default().to_owned());
+            } else {
+                texts.push("<dropped>".to_owned());
+            }
+        }
+        texts
+    }
+
+    #[test]
+    fn drains_lanes_in_priority_order_and_skips_limited_channels() {
+        let now = Instant::now();
+        let mut state = State::new(&Connection::default());
+        for _ in 0..RATE_LIMIT {
+            state.buckets.record("#busy", now);
+        }
+        state.push(privmsg("#quiet", "hmmm"), Priority::Ambient, now);
+        state.push(privmsg("#busy", "reply"), Priority::Reply, now);
+        state.push(privmsg("#quiet", "system"), Priority::System, now);
+
+        let texts = drain(&mut state, now);
+        assert_eq!(texts, vec!["system", "hmmm"]);
+        assert!(matches!(state.pop_ready(now), Next::WaitUntil(at) if at == now + RATE_WINDOW));
+
+        state.buckets.moderator.insert("#busy".to_owned());
+        assert!(matches!(state.pop_ready(now), Next::Message(_)));
+    }
+
+    #[test]
+    fn drops_stale_and_duplicate_messages() {
+        let now = Instant::now();
+        let mut state = State::new(&Connection::default());
+        state.push(privmsg("#chan", "hmmm"), Priority::Ambient, now);
+        state.push(privmsg("#chan", "hmmm"), Priority::Ambient, now);
+        state.push(privmsg("#other", "hmmm"), Priority::Ambient, now);
+        state.push(privmsg("#chan", "late reply"), Priority::Reply, now);
+        state.push(privmsg("#chan", "system"), Priority::System, now);
+        assert_eq!(state.dropped, 1);
+
+        let later = now + Duration::from_secs(30);
+        assert_eq!(drain(&mut state, later), vec!["system", "late reply"]);
+        assert_eq!(state.dropped, 3);
+
+        state.push(privmsg("#chan", "late reply"), Priority::Reply, later);
+        assert_eq!(drain(&mut state, later), vec!["<dropped>"]);
+        assert_eq!(state.dropped, 4);
+    }
+
+    #[test]
+    fn retries_rate_limited_messages_and_pauses_banned_channels() {
+        let now = Instant::now();
+        let mut state = State::new(&Connection::default());
+        state.push(privmsg("#chan", "reply"), Priority::Reply, now);
+        let Next::Message(queued) = state.pop_ready(now) else { panic!("Nothing queued") };
+        state.sent(queued, now);
+
+        state.rejected("#chan", "msg_ratelimit", "Your message was not sent because you are sending messages too quickly.", now);
+        assert_eq!(state.failures["msg_ratelimit"], 1);
+        assert!(matches!(state.pop_ready(now), Next::WaitUntil(at) if at == now + RETRY_BACKOFF));
+        assert_eq!(drain(&mut state, now + RETRY_BACKOFF), vec!["reply"]);
+
+        state.push(privmsg("#chan", "queued"), Priority::Reply, now);
+        state.rejected("#chan", "msg_banned", "You are permanently banned from talking in chan.", now);
+        state.push(privmsg("#chan", "later"), Priority::Reply, now);
+        assert!(matches!(state.pop_ready(now), Next::Empty));
+
+        state.rejected("#other", "msg_timedout", "You are timed out for 5 more seconds.", now);
+        state.push(privmsg("#other", "hi"), Priority::Reply, now);
+        assert!(matches!(state.pop_ready(now), Next::WaitUntil(at) if at == now + Duration::from_secs(5)));
+        assert_eq!(drain(&mut state, now + Duration::from_secs(5)), vec!["hi"]);
+    }
+
+    #[test]
+    fn lets_intentional_duplicates_through_with_a_suffix() {
+        let now = Instant::now();
+        let mut state = State::new(&Connection { duplicate_suffix: true, ..Connection::default() });
+        for _ in 0..3 {
+            state.push(privmsg("#chan", "limesHmm"), Priority::Reply, now);
+            state.push(privmsg("#chan", "limesHmm"), Priority::Reply, now);
+            assert_eq!(drain(&mut state, now).len(), 1);
+        }
+        assert_eq!(state.last_sent["#chan"].0, "limesHmm");
+        state.push(privmsg("#chan", "limesHmm"), Priority::Reply, now);
+        assert_eq!(drain(&mut state, now), vec![format!("limesHmm{}", DUPLICATE_SUFFIX)]);
+    }
+
+    #[test]
+    fn keeps_numbered_chunks_under_the_limit() {
+        let text = "blade ".repeat(10_000);
+        let chunks = split_message(privmsg("#chan", &text));
+        assert!(chunks.len() > 100);
+        for chunk in &chunks {
+            let Command::PRIVMSG(_, chunk) = &chunk.command else { panic!("Not a PRIVMSG") };
+            assert!(chunk.chars().count() <= MESSAGE_LIMIT);
+        }
+    }
+
+    #[test]
+    fn keeps_identical_replies_to_different_messages() {
+        let now = Instant::now();
+        let mut state = State::new(&Connection::default());
+        let reply = |parent: &str| Message {
+            tags: Some(vec![Tag("reply-parent-msg-id".to_owned(), Some(parent.to_owned()))]),
+            prefix: None,
+            command: Command::PRIVMSG("#chan".to_owned(), "[💚] pong".to_owned())
+        };
+        state.push(reply("first"), Priority::Reply, now);
+        state.push(reply("second"), Priority::Reply, now);
+        assert_eq!(drain(&mut state, now), vec!["[💚] pong".to_owned(), format!("[💚] pong{}", DUPLICATE_SUFFIX)]);
+        state.push(reply("second"), Priority::Reply, now);
+        assert_eq!(drain(&mut state, now), vec!["[💚] pong"]);
+        state.push(reply("second"), Priority::Reply, now);
+        assert_eq!(drain(&mut state, now), vec!["<dropped>"]);
+    }
+
+    #[test]
+    fn cuts_long_words_on_char_boundaries() {
+        let chunks = split_text(&"💚".repeat(10), 4);
+        assert_eq!(chunks, vec!["💚💚💚💚", "💚💚💚💚", "💚💚"]);
+    }
+}
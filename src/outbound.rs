@@ -0,0 +1,26 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use irc::client::prelude::Message;
+
+use crate::message_queue::{Priority, QueueStats};
+
+/// Outbound side of the bot: where replies go and how channels are joined
+#[async_trait]
+pub trait Outbound: Send + Sync {
+    async fn send(&self, message: Message, priority: Priority);
+
+    fn join(&self, channel: &str) -> Result<(), Box<dyn Error>>;
+
+    fn part(&self, channel: &str) -> Result<(), Box<dyn Error>>;
+
+    /// State of the outbound queue, if there is one
+    fn stats(&self) -> Option<QueueStats> {
+        None
+    }
+
+    /// Lift a ban or timeout pause on a channel, false if it wasn't paused
+    fn resume(&self, _channel: &str) -> bool {
+        false
+    }
+}
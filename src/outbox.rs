@@ -0,0 +1,170 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use json::{object, JsonValue};
+
+/// Gateway writes that haven't been acknowledged yet, kept in an append-only journal so they survive restarts.
+/// Each line is either `{"post": key, "body": ...}` or `{"done": key}`
+pub struct Outbox {
+    path: PathBuf,
+    state: Mutex<State>
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    pub body: JsonValue
+}
+
+struct State {
+    pending: Vec<Entry>,
+    /// Keys someone is posting right now
+    in_flight: HashSet<String>,
+    /// Keys whose last post may have reached the gateway, only a sync can tell
+    parked: HashSet<String>
+}
+
+impl Outbox {
+    /// Replay the journal, entries without a matching "done" are still pending.
+    /// The file is rewritten with just those, so it doesn't grow forever
+    pub fn open(path: PathBuf) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut pending: Vec<Entry> = Vec::new();
+        if path.exists() {
+            let mut done = HashSet::new();
+            for (number, line) in std::fs::read_to_string(&path)?.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let json = match json::parse(line) {
+                    Ok(json) => json,
+                    Err(e) => {
+                        log::error!("Skipping broken line {} in {}: {}", number + 1, path.display(), e);
+                        continue;
+                    }
+                };
+                if let Some(key) = json["post"].as_str() {
+                    pending.push(Entry { key: key.to_owned(), body: json["body"].clone() });
+                } else if let Some(key) = json["done"].as_str() {
+                    done.insert(key.to_owned());
+                }
+            }
+            pending.retain(|entry| !done.contains(&entry.key));
+        }
+        if !pending.is_empty() {
+            log::info!("{} armory writes pending in {}", pending.len(), path.display());
+        }
+
+        let temp = path.with_extension("tmp");
+        let lines = pending.iter()
+            .map(|entry| json::stringify(object!(post: entry.key.clone(), body: entry.body.clone())) + "\n")
+            .collect::<String>();
+        std::fs::write(&temp, lines)?;
+        std::fs::rename(&temp, &path)?;
+
+        Ok(Self {
+            path,
+            state: Mutex::new(State { pending, in_flight: HashSet::new(), parked: HashSet::new() })
+        })
+    }
+
+    /// Journal a write before it is attempted, the returned key is already claimed by the caller
+    pub fn push(&self, body: JsonValue) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let key = format!("{:x}-{:016x}", chrono::Utc::now().timestamp_millis(), rand::random::<u64>());
+        self.append(object!(post: key.clone(), body: body.clone()))?;
+        let mut state = self.lock();
+        state.pending.push(Entry { key: key.clone(), body });
+        state.in_flight.insert(key.clone());
+        Ok(key)
+    }
+
+    /// The gateway acknowledged the write
+    pub fn done(&self, key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        {
+            let mut state = self.lock();
+            state.pending.retain(|entry| entry.key != key);
+            state.in_flight.remove(key);
+            state.parked.remove(key);
+        }
+        self.append(object!(done: key))
+    }
+
+    /// Pending entries nobody is working on, they stay claimed until `done` or `release`.
+    /// Parked entries are left out
+    pub fn claim(&self) -> Vec<Entry> {
+        let mut state = self.lock();
+        let State { pending, in_flight, parked } = &mut *state;
+        pending.iter()
+            .filter(|entry| !parked.contains(&entry.key) && in_flight.insert(entry.key.clone()))
+            .cloned()
+            .collect()
+    }
+
+    /// Claim one entry, false while someone else holds it
+    pub fn try_claim(&self, key: &str) -> bool {
+        self.lock().in_flight.insert(key.to_owned())
+    }
+
+    /// Attempt failed, let the next retry pick the entry up
+    pub fn release(&self, key: &str) {
+        let mut state = self.lock();
+        state.in_flight.remove(key);
+        state.parked.remove(key);
+    }
+
+    /// Attempt failed in a way that may have left the write behind, retries skip the entry
+    /// until a sync finds it in the store or releases it
+    pub fn park(&self, key: &str) {
+        let mut state = self.lock();
+        state.in_flight.remove(key);
+        state.parked.insert(key.to_owned());
+    }
+
+    pub fn pending(&self) -> Vec<Entry> {
+        self.lock().pending.clone()
+    }
+
+    fn append(&self, line: JsonValue) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all((json::stringify(line) + "\n").as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn keeps_unacknowledged_writes_across_restarts() {
+        let path = test_support::temp_dir("outbox").join("outbox.jsonl");
+        let outbox = Outbox::open(path.clone()).unwrap();
+        let first = outbox.push(object!(owner: "first")).unwrap();
+        let second = outbox.push(object!(owner: "second")).unwrap();
+        assert!(outbox.claim().is_empty());
+        outbox.done(&first).unwrap();
+        outbox.park(&second);
+        assert!(outbox.claim().is_empty());
+        outbox.release(&second);
+        assert_eq!(outbox.claim().len(), 1);
+        assert!(outbox.claim().is_empty());
+        assert!(!outbox.try_claim(&second));
+        drop(outbox);
+
+        let outbox = Outbox::open(path.clone()).unwrap();
+        let pending = outbox.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].key, second);
+        assert_eq!(pending[0].body["owner"], "second");
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+    }
+}
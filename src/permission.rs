@@ -0,0 +1,105 @@
+use std::fmt;
+use std::error::Error;
+
+use crate::twitch::TwitchMessage;
+
+/// Who is allowed to use a command, ordered from least to most privileged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    Everyone,
+    Subscriber,
+    Vip,
+    Moderator,
+    Broadcaster,
+    Owner,
+}
+
+impl Permission {
+    pub fn parse(string: &str) -> Result<Self, Box<dyn Error>> {
+        match string {
+            "everyone" => Ok(Permission::Everyone),
+            "subscriber" => Ok(Permission::Subscriber),
+            "vip" => Ok(Permission::Vip),
+            "moderator" => Ok(Permission::Moderator),
+            "broadcaster" => Ok(Permission::Broadcaster),
+            "owner" => Ok(Permission::Owner),
+            _ => Err(format!("Unknown permission level: {}", string).into())
+        }
+    }
+
+    /// Highest level the sender of a message has, based on the IRCv3 tags.
+    /// Owners are matched on either their user id or login
+    pub fn of(message: &TwitchMessage, owners: &[String]) -> Self {
+        if owners.iter().any(|owner| Some(owner) == message.user_id.as_ref()
+            || owner.to_lowercase() == message.login.to_lowercase())
+        {
+            return Permission::Owner;
+        }
+
+        let mut level = Permission::Everyone;
+        for badge in &message.badges {
+            let badge = match badge.name.as_str() {
+                "broadcaster" => Permission::Broadcaster,
+                "moderator" => Permission::Moderator,
+                "vip" => Permission::Vip,
+                "subscriber" | "founder" => Permission::Subscriber,
+                _ => Permission::Everyone
+            };
+            level = level.max(badge);
+        }
+        if message.moderator {
+            level = level.max(Permission::Moderator);
+        }
+        if message.vip {
+            level = level.max(Permission::Vip);
+        }
+        if message.subscriber {
+            level = level.max(Permission::Subscriber);
+        }
+        level
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = match *self {
+            Permission::Everyone => "everyone",
+            Permission::Subscriber => "subscriber",
+            Permission::Vip => "vip",
+            Permission::Moderator => "moderator",
+            Permission::Broadcaster => "broadcaster",
+            Permission::Owner => "owner",
+        };
+        write!(f, "{}", level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use irc::client::prelude::Message;
+
+    fn of(tags: &str, owners: &[&str]) -> Permission {
+        let raw = format!("@{} :someone!someone@someone.tmi.twitch.tv PRIVMSG #chan :hi", tags);
+        let message = TwitchMessage::parse(&raw.parse::<Message>().unwrap()).unwrap();
+        Permission::of(&message, &owners.iter().map(|o| o.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn reads_levels_from_badges_and_flags() {
+        assert_eq!(of("badges=", &[]), Permission::Everyone);
+        assert_eq!(of("badges=founder/0", &[]), Permission::Subscriber);
+        assert_eq!(of("badges=subscriber/12,vip/1", &[]), Permission::Vip);
+        assert_eq!(of("badges=broadcaster/1,subscriber/0", &[]), Permission::Broadcaster);
+        assert_eq!(of("subscriber=1", &[]), Permission::Subscriber);
+        assert_eq!(of("vip=1;subscriber=1", &[]), Permission::Vip);
+        assert_eq!(of("mod=1;badges=vip/1", &[]), Permission::Moderator);
+    }
+
+    #[test]
+    fn matches_owners_by_id_or_login() {
+        assert_eq!(of("user-id=42", &["42"]), Permission::Owner);
+        assert_eq!(of("user-id=42", &["SomeOne"]), Permission::Owner);
+        assert_eq!(of("user-id=42;badges=broadcaster/1", &["7", "other"]), Permission::Broadcaster);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    error::Error,
+    fmt,
+    time::Duration
+};
+
+use np_utils::get_env_var;
+use rand::Rng;
+
+/// Error that retrying won't fix: a rejected token, missing env var or broken config
+#[derive(Debug)]
+pub struct Fatal(pub String);
+
+impl fmt::Display for Fatal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for Fatal {}
+
+pub fn is_fatal(error: &(dyn Error + 'static)) -> bool {
+    error.downcast_ref::<Fatal>().is_some()
+}
+
+/// Server asked for a reconnect, e.g. Twitch restarting an edge server
+#[derive(Debug)]
+pub struct Requested;
+
+impl fmt::Display for Requested {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Server requested a reconnect")
+    }
+}
+
+impl Error for Requested {}
+
+pub fn is_requested(error: &(dyn Error + 'static)) -> bool {
+    error.downcast_ref::<Requested>().is_some()
+}
+
+/// Exponential backoff with jitter between reconnect attempts
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    /// None retries forever
+    max_attempts: Option<u32>,
+    /// A connection that lasted this long starts the count over
+    healthy: Duration,
+    attempts: u32
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration, max_attempts: Option<u32>, healthy: Duration) -> Self {
+        Self {
+            base,
+            max,
+            max_attempts,
+            healthy,
+            attempts: 0
+        }
+    }
+
+    /// Settings from NPBOT_RECONNECT_ATTEMPTS (0 for unlimited), NPBOT_RECONNECT_BASE_MS,
+    /// NPBOT_RECONNECT_MAX_MS and NPBOT_HEALTHY_SECS
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let number = |name: &str, default: &str| get_env_var(name, default).parse::<u64>()
+            .map_err(|e| Fatal(format!("Invalid {}: {}", name, e)));
+        let attempts = number("NPBOT_RECONNECT_ATTEMPTS", "5")?;
+        Ok(Self::new(
+            Duration::from_millis(number("NPBOT_RECONNECT_BASE_MS", "1000")?),
+            Duration::from_millis(number("NPBOT_RECONNECT_MAX_MS", "300000")?),
+            if attempts == 0 { None } else { Some(attempts as u32) },
+            Duration::from_secs(number("NPBOT_HEALTHY_SECS", "300")?)
+        ))
+    }
+
+    /// Called when a connection ends, a long enough one resets the attempts
+    pub fn connected_for(&mut self, uptime: Duration) {
+        if uptime >= self.healthy && self.attempts > 0 {
+            log::info!("Connection was healthy for {}s, resetting backoff", uptime.as_secs());
+            self.attempts = 0;
+        }
+    }
+
+    /// Delay before the next attempt, None once all attempts are used up.
+    /// Half of the delay is fixed and half is random, so restarts don't line up
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| self.attempts >= max) {
+            return None;
+        }
+        let delay = self.base
+            .saturating_mul(2u32.saturating_pow(self.attempts))
+            .min(self.max);
+        self.attempts += 1;
+        let half = delay.as_millis() as u64 / 2;
+        Some(Duration::from_millis(half + rand::rng().random_range(0..=half)))
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn max_attempts(&self) -> Option<u32> {
+        self.max_attempts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_with_jitter_until_attempts_run_out() {
+        let base = Duration::from_millis(100);
+        let mut backoff = Backoff::new(base, Duration::from_millis(300), Some(4), Duration::from_secs(60));
+        for max in [100, 200, 300, 300] {
+            let delay = backoff.next_delay().expect("Attempts ran out early");
+            assert!(delay >= Duration::from_millis(max / 2) && delay <= Duration::from_millis(max));
+        }
+        assert_eq!(backoff.next_delay(), None);
+
+        backoff.connected_for(Duration::from_secs(1));
+        assert_eq!(backoff.next_delay(), None);
+        backoff.connected_for(Duration::from_secs(60));
+        assert!(backoff.next_delay().is_some_and(|delay| delay <= base));
+    }
+
+    #[test]
+    fn retries_forever_without_a_limit() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(30), None, Duration::from_secs(60));
+        assert!((0..100).all(|_| backoff.next_delay().is_some_and(|d| d <= Duration::from_secs(30))));
+    }
+
+    #[test]
+    fn recognizes_fatal_errors() {
+        let fatal: Box<dyn Error> = Fatal("bad token".to_owned()).into();
+        let transient: Box<dyn Error> = "connection reset".into();
+        assert!(is_fatal(&*fatal));
+        assert!(!is_fatal(&*transient));
+    }
+}
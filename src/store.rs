@@ -0,0 +1,241 @@
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use json::JsonValue;
+use tokio::sync::Mutex;
+
+use crate::gateway::{Gateway, GatewayError};
+use crate::reconnect::Fatal;
+
+/// Where swords are kept, as the JSON the gateway speaks
+#[async_trait]
+pub trait ArmoryStore: Send + Sync {
+    /// One page of swords, starting from 1, and whether more follow
+    async fn page(&self, page: u32, per_page: u32) -> Result<(Vec<JsonValue>, bool), Box<dyn Error + Send + Sync>>;
+
+    /// Store a new sword, returns the id it was given. A sword with the `client_id` of one already stored
+    /// is the same post made again, it gets that sword's id and nothing is added
+    async fn create(&self, sword: JsonValue) -> Result<i64, Box<dyn Error + Send + Sync>>;
+
+    /// Overwrite some fields of a stored sword, returns the sword as stored now
+    async fn update(&self, id: i64, changes: JsonValue) -> Result<JsonValue, Box<dyn Error + Send + Sync>>;
+}
+
+#[async_trait]
+impl ArmoryStore for Gateway {
+    async fn page(&self, page: u32, per_page: u32) -> Result<(Vec<JsonValue>, bool), Box<dyn Error + Send + Sync>> {
+        let params = HashMap::from([
+            ("page", page.to_string()),
+            ("per_page", per_page.to_string())
+        ]);
+        let json = self.get("/armory", params).await?;
+        if !json["data"].is_array() || !json["meta"]["has_next"].is_boolean() {
+            return Err(format!("Result is not valid: {}", json).into())
+        }
+        let has_next = json["meta"]["has_next"].as_bool().unwrap_or(false);
+        Ok((json["data"].members().cloned().collect(), has_next))
+    }
+
+    async fn create(&self, sword: JsonValue) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        // The gateway took the sword whatever it answered, so these are gateway errors that aren't worth a retry
+        match self.post("/armory", sword).await? {
+            Some(json) => json["id"].as_i64().ok_or(GatewayError::Decode(format!("No id in response: {}", json)).into()),
+            None => Err(GatewayError::Decode("Empty response".to_owned()).into())
+        }
+    }
+
+    async fn update(&self, id: i64, changes: JsonValue) -> Result<JsonValue, Box<dyn Error + Send + Sync>> {
+        match self.patch(format!("/armory/{}", id).as_str(), changes).await? {
+            Some(json) if json["id"].as_i64() == Some(id) => Ok(json),
+            Some(json) => Err(format!("Unexpected sword in response: {}", json).into()),
+            None => Err("Empty response".into())
+        }
+    }
+}
+
+/// Swords that live as long as the process, for development and tests
+pub struct MemoryStore {
+    swords: Mutex<Vec<JsonValue>>
+}
+
+impl MemoryStore {
+    pub fn new(swords: Vec<JsonValue>) -> Self {
+        Self { swords: Mutex::new(swords) }
+    }
+
+    /// Everything stored so far
+    #[cfg(test)]
+    pub async fn swords(&self) -> Vec<JsonValue> {
+        self.swords.lock().await.clone()
+    }
+}
+
+#[async_trait]
+impl ArmoryStore for MemoryStore {
+    async fn page(&self, page: u32, per_page: u32) -> Result<(Vec<JsonValue>, bool), Box<dyn Error + Send + Sync>> {
+        Ok(paginate(&self.swords.lock().await, page, per_page))
+    }
+
+    async fn create(&self, mut sword: JsonValue) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let mut swords = self.swords.lock().await;
+        if let Some(id) = posted(&swords, &sword) {
+            return Ok(id);
+        }
+        let id = next_id(&swords);
+        sword["id"] = id.into();
+        swords.push(sword);
+        Ok(id)
+    }
+
+    async fn update(&self, id: i64, changes: JsonValue) -> Result<JsonValue, Box<dyn Error + Send + Sync>> {
+        let mut swords = self.swords.lock().await;
+        let sword = swords.iter_mut().find(|s| s["id"].as_i64() == Some(id)).ok_or(format!("No sword #{}", id))?;
+        for (key, value) in changes.entries() {
+            sword[key] = value.clone();
+        }
+        Ok(sword.clone())
+    }
+}
+
+/// Swords kept in a local file, one JSON object per line, so the bot can run without a gateway.
+/// Updates append the whole sword again, the last line with an id wins
+pub struct FileStore {
+    path: PathBuf,
+    swords: Mutex<Vec<JsonValue>>
+}
+
+impl FileStore {
+    pub fn open(path: PathBuf) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut swords = Vec::new();
+        if path.exists() {
+            for (number, line) in std::fs::read_to_string(&path)?.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match json::parse(line) {
+                    Ok(sword) => match swords.iter_mut().find(|s| s["id"] == sword["id"]) {
+                        Some(stored) => *stored = sword,
+                        None => swords.push(sword)
+                    },
+                    Err(e) => log::error!("Skipping broken line {} in {}: {}", number + 1, path.display(), e)
+                }
+            }
+        }
+        log::info!("{} swords in {}", swords.len(), path.display());
+        Ok(Self { path, swords: Mutex::new(swords) })
+    }
+
+    fn append(&self, sword: &JsonValue) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all((json::stringify(sword.clone()) + "\n").as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ArmoryStore for FileStore {
+    async fn page(&self, page: u32, per_page: u32) -> Result<(Vec<JsonValue>, bool), Box<dyn Error + Send + Sync>> {
+        Ok(paginate(&self.swords.lock().await, page, per_page))
+    }
+
+    async fn create(&self, mut sword: JsonValue) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let mut swords = self.swords.lock().await;
+        if let Some(id) = posted(&swords, &sword) {
+            return Ok(id);
+        }
+        let id = next_id(&swords);
+        sword["id"] = id.into();
+        self.append(&sword)?;
+        swords.push(sword);
+        Ok(id)
+    }
+
+    async fn update(&self, id: i64, changes: JsonValue) -> Result<JsonValue, Box<dyn Error + Send + Sync>> {
+        let mut swords = self.swords.lock().await;
+        let stored = swords.iter_mut().find(|s| s["id"].as_i64() == Some(id)).ok_or(format!("No sword #{}", id))?;
+        let mut updated = stored.clone();
+        for (key, value) in changes.entries() {
+            updated[key] = value.clone();
+        }
+        self.append(&updated)?;
+        *stored = updated.clone();
+        Ok(updated)
+    }
+}
+
+/// Which store to use from NPBOT_ARMORY: "gateway" (the default), "memory" or "file:<path>".
+/// The gateway is only built when it is picked, so the other two don't need its settings
+pub fn from_env(gateway: impl FnOnce() -> Result<Gateway, Box<dyn Error>>) -> Result<Arc<dyn ArmoryStore>, Box<dyn Error>> {
+    let setting = np_utils::get_env_var("NPBOT_ARMORY", "gateway");
+    Ok(match setting.as_str() {
+        "gateway" => Arc::new(gateway()?),
+        "memory" => {
+            log::warn!("Armory is kept in memory, swords will be lost on exit");
+            Arc::new(MemoryStore::new(Vec::new()))
+        },
+        other => match other.strip_prefix("file:") {
+            Some(path) => Arc::new(FileStore::open(PathBuf::from(path))
+                .map_err(|e| Fatal(format!("Failed to open armory file {}: {}", path, e)))?),
+            None => return Err(Fatal(format!("Unknown armory store {}", other)).into())
+        }
+    })
+}
+
+fn paginate(swords: &[JsonValue], page: u32, per_page: u32) -> (Vec<JsonValue>, bool) {
+    let (page, per_page) = (page.max(1) as usize, per_page.max(1) as usize);
+    let data = swords.iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .cloned()
+        .collect();
+    (data, swords.len() > page * per_page)
+}
+
+/// Id of the sword stored under the same `client_id`, if there is one
+fn posted(swords: &[JsonValue], sword: &JsonValue) -> Option<i64> {
+    let client_id = sword["client_id"].as_str()?;
+    swords.iter().find(|s| s["client_id"].as_str() == Some(client_id))?["id"].as_i64()
+}
+
+fn next_id(swords: &[JsonValue]) -> i64 {
+    swords.iter().filter_map(|s| s["id"].as_i64()).max().unwrap_or(0) + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::object;
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn file_store_keeps_swords_across_restarts() {
+        let path = test_support::temp_dir("file-store").join("armory.jsonl");
+        let store = FileStore::open(path.clone()).unwrap();
+        assert_eq!(store.create(object!(owner: "first")).await.unwrap(), 1);
+        assert_eq!(store.create(object!(owner: "second")).await.unwrap(), 2);
+        drop(store);
+
+        let store = FileStore::open(path).unwrap();
+        let (page, has_next) = store.page(2, 1).await.unwrap();
+        assert!(!has_next);
+        assert_eq!(page[0]["id"], 2);
+        assert_eq!(page[0]["owner"], "second");
+        assert_eq!(store.update(1, object!(owner: "third")).await.unwrap()["owner"], "third");
+        assert!(store.update(5, object!(owner: "third")).await.is_err());
+        drop(store);
+
+        let store = FileStore::open(path).unwrap();
+        let (page, _) = store.page(1, 10).await.unwrap();
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0]["owner"], "third");
+        assert_eq!(store.create(object!(owner: "fourth", client_id: "key")).await.unwrap(), 3);
+        assert_eq!(store.create(object!(owner: "fourth", client_id: "key")).await.unwrap(), 3);
+        assert_eq!(store.page(1, 10).await.unwrap().0.len(), 3);
+    }
+}
@@ -0,0 +1,385 @@
+//! In-process fakes of Twitch IRC and the armory gateway for end-to-end tests
+
+use std::{
+    error::Error,
+    path::PathBuf,
+    sync::{Arc, atomic::{AtomicUsize, Ordering}},
+    time::Duration
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::{mpsc, Mutex, Notify}
+};
+
+use crate::{armory, chatters::Chatters, cooldown::Cooldowns, duel, gateway, irc::{self, Providers}, moon, shutdown::Shutdown, store::ArmoryStore, trade};
+
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fresh directory under the system temp dir for files the bot reads and writes
+pub fn temp_dir(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "np-bot-{}-{}-{}",
+        name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)));
+    std::fs::create_dir_all(&dir).expect("Failed to create temp dir");
+    dir
+}
+
+/// Config file pointing the bot at a fake IRC server
+pub fn write_config(dir: &PathBuf, port: u16, channels: &[(&str, &[&str])]) -> PathBuf {
+    let path = dir.join("ircconfig.json");
+    let channels = channels.iter().map(|(name, features)| json::object!(
+        active: true,
+        name: *name,
+        features: features.to_vec()
+    )).collect::<Vec<_>>();
+    let config = json::object!(
+        owners: ["owner"],
+        connection: json::object!(
+            nickname: "testbot",
+            server: "127.0.0.1",
+            port: port,
+            use_tls: false,
+            queue_delay_ms: 0
+        ),
+        channels: channels
+    );
+    std::fs::write(&path, json::stringify_pretty(config, 4)).expect("Failed to write config");
+    path
+}
+
+/// Bot connected to a fresh fake IRC server, with an empty armory and owner "owner".
+/// Returns the server, the config file and the bot's loop
+pub async fn start_bot(name: &str, channels: &[(&str, &[&str])], shutdown: Shutdown) -> (
+    FakeIrcServer,
+    PathBuf,
+    tokio::task::JoinHandle<Result<(), Box<dyn Error + Send + Sync>>>
+) {
+    let server = FakeIrcServer::start().await;
+    let gateway = FakeGateway::start(Vec::new()).await;
+    let dir = temp_dir(name);
+    let config_path = write_config(&dir, server.port, channels);
+    let providers = providers(&gateway, &dir).await;
+    let handle = irc::connect(
+        "oauth:test",
+        "safeword".to_owned(),
+        config_path.clone(),
+        &irc::ConfigWatch::start(config_path.clone()),
+        dir.join("history.csv"),
+        dir.join("users.txt"),
+        providers,
+        shutdown
+    ).await.expect("Failed to connect to fake server");
+    (server, config_path, handle)
+}
+
+/// Providers backed by a fake gateway, no network access outside localhost
+pub async fn providers(gateway: &FakeGateway, dir: &PathBuf) -> Providers {
+    let gateway = gateway::Gateway::init(gateway.url(), "secret".to_owned()).expect("Failed to init gateway");
+    providers_with(Arc::new(gateway), dir).await
+}
+
+/// Providers keeping the armory in the given store
+pub async fn providers_with(armory: Arc<dyn ArmoryStore>, dir: &PathBuf) -> Providers {
+    let affinity = dir.join("affinity.csv");
+    std::fs::write(&affinity, "").expect("Failed to write affinity file");
+    Providers {
+        swords: Arc::new(armory::Swords::new(dir.join("elven.txt"), dir.join("outbox.jsonl"), Arc::clone(&armory)).await
+            .expect("Failed to init swords")),
+        tarot: Arc::new(np_tarot::Tarot::new(affinity).expect("Failed to init tarot")),
+        moon: Arc::new(moon::init("http://127.0.0.1:1/moon".to_owned()).expect("Failed to init moon")),
+        armory,
+        duels: Arc::new(duel::Duels::open(dir.join("duels.jsonl")).expect("Failed to init duels")),
+        trades: Arc::new(trade::Trades::new()),
+        chatters: Arc::new(Chatters::new()),
+        cooldowns: Arc::new(Cooldowns::new())
+    }
+}
+
+/// Speaks enough Twitch IRC for the bot: registration, CAP, JOIN/PART, PING and tagged PRIVMSG.
+/// Accepts connections one after another, so reconnects can be observed
+pub struct FakeIrcServer {
+    pub port: u16,
+    received: Arc<Mutex<Vec<String>>>,
+    received_notify: Arc<Notify>,
+    inject: mpsc::UnboundedSender<String>
+}
+
+impl FakeIrcServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind fake IRC server");
+        let port = listener.local_addr().expect("No local address").port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_notify = Arc::new(Notify::new());
+        let (inject, mut inject_rx) = mpsc::unbounded_channel::<String>();
+
+        let received_ref = Arc::clone(&received);
+        let notify_ref = Arc::clone(&received_notify);
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let (reader, mut writer) = socket.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                let mut nickname = String::from("unknown");
+                loop {
+                    tokio::select! {
+                        line = lines.next_line() => {
+                            let line = match line {
+                                Ok(Some(line)) => line,
+                                _ => break
+                            };
+                            received_ref.lock().await.push(line.clone());
+                            notify_ref.notify_waiters();
+                            let reply = respond(&line, &mut nickname);
+                            for reply in reply {
+                                if writer.write_all(format!("{}\r\n", reply).as_bytes()).await.is_err() {
+                                    break;
+                                }
+                            }
+                        },
+                        line = inject_rx.recv() => {
+                            let line = match line {
+                                Some(line) => line,
+                                None => return
+                            };
+                            if writer.write_all(format!("{}\r\n", line).as_bytes()).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            port,
+            received,
+            received_notify,
+            inject
+        }
+    }
+
+    /// Send a raw line to the connected bot
+    pub fn send(&self, line: &str) {
+        self.inject.send(line.to_owned()).expect("Fake IRC server stopped");
+    }
+
+    /// Send a chat message from `user` with the given IRCv3 tags
+    pub fn privmsg(&self, channel: &str, user: &str, text: &str, tags: &[(&str, &str)]) {
+        let tags = if tags.is_empty() {
+            String::new()
+        } else {
+            format!("@{} ", tags.iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join(";"))
+        };
+        self.send(format!("{}:{2}!{2}@{2}.tmi.twitch.tv PRIVMSG {} :{}", tags, channel, user, text).as_str());
+    }
+
+    /// All lines received from the bot so far
+    pub async fn received(&self) -> Vec<String> {
+        self.received.lock().await.clone()
+    }
+
+    /// Wait for a line from the bot matching the predicate, skipping the first `skip` lines
+    pub async fn wait_for<P>(&self, skip: usize, predicate: P) -> Option<String>
+    where
+        P: Fn(&str) -> bool
+    {
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                let notified = self.received_notify.notified();
+                if let Some(line) = self.received.lock().await.iter().skip(skip).find(|l| predicate(l)) {
+                    return line.clone();
+                }
+                notified.await;
+            }
+        }).await.ok()
+    }
+}
+
+fn respond(line: &str, nickname: &mut String) -> Vec<String> {
+    let mut words = line.split_whitespace();
+    match words.next() {
+        Some("NICK") => {
+            *nickname = words.next().unwrap_or("unknown").to_owned();
+            vec![format!(":tmi.twitch.tv 001 {} :Welcome, GLHF!", nickname)]
+        },
+        Some("CAP") if words.next() == Some("REQ") => {
+            let capabilities = line.split_once(" :").map(|(_, c)| c).unwrap_or("");
+            vec![format!(":tmi.twitch.tv CAP * ACK :{}", capabilities)]
+        },
+        Some("JOIN") | Some("PART") => {
+            let command = if line.starts_with("JOIN") { "JOIN" } else { "PART" };
+            words.next().unwrap_or("").split(',')
+                .map(|channel| format!(":{0}!{0}@{0}.tmi.twitch.tv {1} {2}", nickname, command, channel))
+                .collect()
+        },
+        Some("PING") => vec![format!(":tmi.twitch.tv PONG tmi.twitch.tv {}", words.next().unwrap_or(":tmi.twitch.tv"))],
+        _ => Vec::new()
+    }
+}
+
+/// Minimal HTTP server implementing the armory endpoints:
+/// paginated `GET /armory?page=&per_page=`, `POST /armory` returning the new id
+/// (or the old one when the `client_id` was posted before)
+/// and `PATCH /armory/<id>` returning the updated sword
+pub struct FakeGateway {
+    pub port: u16,
+    swords: Arc<Mutex<Vec<json::JsonValue>>>
+}
+
+impl FakeGateway {
+    pub async fn start(swords: Vec<json::JsonValue>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind fake gateway");
+        let port = listener.local_addr().expect("No local address").port();
+        let swords = Arc::new(Mutex::new(swords));
+        let swords_ref = Arc::clone(&swords);
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let swords = Arc::clone(&swords_ref);
+                tokio::spawn(async move {
+                    let (reader, mut writer) = socket.into_split();
+                    let mut reader = BufReader::new(reader);
+                    let (method, target, body) = match read_request(&mut reader).await {
+                        Some(request) => request,
+                        None => return
+                    };
+                    let (status, body) = route(&method, &target, &body, &swords).await;
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status, body.len(), body);
+                    let _ = writer.write_all(response.as_bytes()).await;
+                    let _ = writer.shutdown().await;
+                });
+            }
+        });
+        Self { port, swords }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}/", self.port)
+    }
+
+    /// Swords stored on the fake gateway
+    pub async fn swords(&self) -> Vec<json::JsonValue> {
+        self.swords.lock().await.clone()
+    }
+
+    /// Edit the stored swords behind the bot's back
+    pub async fn set_swords(&self, swords: Vec<json::JsonValue>) {
+        *self.swords.lock().await = swords;
+    }
+}
+
+async fn read_request<R>(reader: &mut BufReader<R>) -> Option<(String, String, String)>
+where
+    R: tokio::io::AsyncRead + Unpin
+{
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await.ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let target = parts.next()?.to_owned();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await.ok()?;
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await.ok()?;
+    Some((method, target, String::from_utf8(body).ok()?))
+}
+
+async fn route(
+    method: &str,
+    target: &str,
+    body: &str,
+    swords: &Mutex<Vec<json::JsonValue>>
+) -> (&'static str, String) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let param = |name: &str| query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .and_then(|(_, value)| value.parse::<usize>().ok());
+    match (method, path) {
+        ("GET", "/armory") => {
+            let page = param("page").unwrap_or(1).max(1);
+            let per_page = param("per_page").unwrap_or(100).max(1);
+            let swords = swords.lock().await;
+            let data = swords.iter()
+                .skip((page - 1) * per_page)
+                .take(per_page)
+                .cloned()
+                .collect::<Vec<_>>();
+            let has_next = swords.len() > page * per_page;
+            ("200 OK", json::stringify(json::object!(
+                data: data,
+                meta: json::object!(has_next: has_next)
+            )))
+        },
+        ("POST", "/armory") => {
+            let mut sword = match json::parse(body) {
+                Ok(sword) => sword,
+                Err(_) => return ("400 Bad Request", "{}".to_owned())
+            };
+            let mut swords = swords.lock().await;
+            let posted = sword["client_id"].as_str()
+                .and_then(|key| swords.iter().find(|s| s["client_id"].as_str() == Some(key)))
+                .and_then(|s| s["id"].as_i64());
+            if let Some(id) = posted {
+                return ("200 OK", json::stringify(json::object!(id: id)));
+            }
+            let id = swords.len() as i64 + 1;
+            sword["id"] = id.into();
+            swords.push(sword);
+            ("201 Created", json::stringify(json::object!(id: id)))
+        },
+        ("PATCH", path) if path.starts_with("/armory/") => {
+            let id = path.trim_start_matches("/armory/").parse::<i64>().ok();
+            let changes = match json::parse(body) {
+                Ok(changes) => changes,
+                Err(_) => return ("400 Bad Request", "{}".to_owned())
+            };
+            let mut swords = swords.lock().await;
+            match swords.iter_mut().find(|s| s["id"].as_i64() == id) {
+                Some(sword) => {
+                    for (key, value) in changes.entries() {
+                        sword[key] = value.clone();
+                    }
+                    ("200 OK", json::stringify(sword.clone()))
+                },
+                None => ("404 Not Found", "{}".to_owned())
+            }
+        },
+        _ => ("404 Not Found", "{}".to_owned())
+    }
+}
+
+/// Sword as stored by the gateway
+pub fn sword(id: i64, owner: &str) -> json::JsonValue {
+    json::object!(
+        id: id,
+        material: "steel",
+        handle: "None",
+        sword_type: "katana",
+        quality: "+",
+        name: null,
+        real_name: null,
+        owner: owner
+    )
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::chatters::Chatter;
+use crate::commands::Command;
+use crate::config::FeatureKey;
+use crate::irc::Context;
+use crate::twitch::TwitchMessage;
+
+/// How long an offer waits for `!trade accept`
+pub const OFFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Offer {
+    pub from: Chatter,
+    /// Sword the offering side gives away
+    pub mine: i64,
+    /// Sword they want in return
+    pub theirs: i64,
+    expires: Instant
+}
+
+/// Open trade offers by channel and login of who they are made to
+pub struct Trades {
+    offers: Mutex<HashMap<(String, String), Offer>>
+}
+
+impl Trades {
+    pub fn new() -> Self {
+        Self {
+            offers: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Offer `mine` for `theirs`, fails while someone else's offer to `to` is still open
+    pub fn offer(&self, channel: &str, from: &Chatter, to: &Chatter, mine: i64, theirs: i64, now: Instant) -> Result<(), String> {
+        let mut offers = self.offers.lock().unwrap_or_else(|e| e.into_inner());
+        offers.retain(|_, o| o.expires > now);
+        let key = (channel.to_owned(), to.login.clone());
+        if let Some(open) = offers.get(&key) {
+            if open.from.login != from.login {
+                return Err(format!("{} is still considering an offer from {}", to.name, open.from.name));
+            }
+        }
+        offers.insert(key, Offer { from: from.clone(), mine, theirs, expires: now + OFFER_TIMEOUT });
+        Ok(())
+    }
+
+    /// Take the open offer made to the `to` login, whether it ends up accepted or declined
+    pub fn take(&self, channel: &str, to: &str, now: Instant) -> Option<Offer> {
+        let mut offers = self.offers.lock().unwrap_or_else(|e| e.into_inner());
+        offers.remove(&(channel.to_owned(), to.to_lowercase())).filter(|o| o.expires > now)
+    }
+}
+
+/// `!give @user #id` hands one of your swords to someone else
+pub struct Give;
+
+#[async_trait]
+impl Command for Give {
+    fn name(&self) -> &str {
+        "!give"
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Trade
+    }
+
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let user = Chatter::of(message);
+        let reply = match args {
+            [target, id] => match parse_id(id) {
+                Some(id) => give(ctx, &user, target.trim_start_matches('@'), id).await,
+                None => "[💚] Usage: !give @user #id".to_owned()
+            },
+            _ => "[💚] Usage: !give @user #id".to_owned()
+        };
+        log::info!("{}: {}", message.channel, reply);
+        ctx.reply_or_send(message, reply.as_str()).await?;
+        Ok(false)
+    }
+}
+
+/// `!trade #mine #theirs @user` offers a swap, the other side answers with `!trade accept` or `!trade decline`
+pub struct Trade;
+
+#[async_trait]
+impl Command for Trade {
+    fn name(&self) -> &str {
+        "!trade"
+    }
+
+    fn feature(&self) -> FeatureKey {
+        FeatureKey::Trade
+    }
+
+    async fn execute(&self, ctx: &Context, message: &TwitchMessage, args: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let user = Chatter::of(message);
+        let channel = message.channel.as_str();
+        let reply = match args {
+            ["accept"] => match ctx.trades.take(channel, &user.login, Instant::now()) {
+                Some(offer) => swap(ctx, &offer, &user).await,
+                None => format!("[💚] Nobody is offering you anything, {}.", user.name)
+            },
+            ["decline"] => match ctx.trades.take(channel, &user.login, Instant::now()) {
+                Some(offer) => format!("[💚] {} turns down {}'s offer.", user.name, offer.from.name),
+                None => format!("[💚] Nobody is offering you anything, {}.", user.name)
+            },
+            [mine, theirs, target] => match (parse_id(mine), parse_id(theirs)) {
+                (Some(mine), Some(theirs)) => offer(ctx, channel, &user, target.trim_start_matches('@'), mine, theirs).await,
+                _ => "[💚] Usage: !trade #mine #theirs @user, !trade accept|decline".to_owned()
+            },
+            _ => "[💚] Usage: !trade #mine #theirs @user, !trade accept|decline".to_owned()
+        };
+        log::info!("{}: {}", channel, reply);
+        ctx.reply_or_send(message, reply.as_str()).await?;
+        Ok(false)
+    }
+}
+
+async fn give(ctx: &Context, user: &Chatter, target: &str, id: i64) -> String {
+    if target.eq_ignore_ascii_case(&user.login) {
+        return "[💚] You pass the blade from one hand to the other.".to_owned();
+    }
+    if ctx.swords.blade(user, Some(id)).await.is_none() {
+        return format!("[💚] #{} is not yours to give.", id);
+    }
+    // A typo would hand the sword to nobody, only people the bot has met can receive one
+    let Some(target) = ctx.chatter(target).await else {
+        return format!("[💚] Nobody here answers to {}, #{} stays with you.", target, id);
+    };
+    match ctx.swords.transfer(Arc::clone(&ctx.armory), id, user, &target).await {
+        Ok(sword) => format!("[💚] {} hands {} (#{}) to {}.", user.name, sword.title(), id, target.name),
+        Err(_) => format!("[💚] #{} is not yours to give.", id)
+    }
+}
+
+async fn offer(ctx: &Context, channel: &str, user: &Chatter, target: &str, mine: i64, theirs: i64) -> String {
+    if target.eq_ignore_ascii_case(&user.login) {
+        return "[💚] You strike a hard bargain with yourself.".to_owned();
+    }
+    let Some(my_sword) = ctx.swords.blade(user, Some(mine)).await else {
+        return format!("[💚] #{} is not yours to trade.", mine);
+    };
+    let Some(target) = ctx.chatter(target).await else {
+        return format!("[💚] Nobody here answers to {}.", target);
+    };
+    let Some(their_sword) = ctx.swords.blade(&target, Some(theirs)).await else {
+        return format!("[💚] #{} does not belong to {}.", theirs, target.name);
+    };
+    match ctx.trades.offer(channel, user, &target, mine, theirs, Instant::now()) {
+        Ok(()) => format!("[💚] {} offers {} (#{}) for {}'s {} (#{}). {}, type !trade accept or !trade decline within {} seconds.",
+            user.name, my_sword.title(), mine, target.name, their_sword.title(), theirs, target.name, OFFER_TIMEOUT.as_secs()),
+        Err(e) => format!("[💚] {}.", e)
+    }
+}
+
+async fn swap(ctx: &Context, offer: &Offer, user: &Chatter) -> String {
+    // Either sword may have changed hands while the offer was open
+    if let Err(e) = ctx.swords.exchange(Arc::clone(&ctx.armory), (offer.mine, &offer.from), (offer.theirs, user)).await {
+        log::info!("Trade between {} and {} is off: {}", offer.from.login, user.login, e);
+        return "[💚] The swords on the table are no longer the same, the deal is off.".to_owned();
+    }
+    format!("[💚] The deal is struck: {} now holds #{} and {} holds #{}.", offer.from.name, offer.theirs, user.name, offer.mine)
+}
+
+fn parse_id(arg: &str) -> Option<i64> {
+    arg.trim_start_matches('#').parse::<i64>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{ArmoryStore, MemoryStore};
+    use crate::test_support;
+
+    #[test]
+    fn offers_expire_and_are_taken_once() {
+        let trades = Trades::new();
+        let now = Instant::now();
+        let (first, second, third) = (Chatter::named("First"), Chatter::named("Second"), Chatter::named("Third"));
+        trades.offer("#chan", &first, &second, 1, 2, now).unwrap();
+        assert!(trades.offer("#chan", &third, &second, 3, 2, now).is_err());
+        let offer = trades.take("#chan", "Second", now).unwrap();
+        assert_eq!((offer.from.name.as_str(), offer.mine, offer.theirs), ("First", 1, 2));
+        assert_eq!(trades.take("#chan", "second", now), None);
+
+        trades.offer("#chan", &first, &second, 1, 2, now).unwrap();
+        assert_eq!(trades.take("#chan", "second", now + OFFER_TIMEOUT), None);
+    }
+
+    #[tokio::test]
+    async fn transfers_record_provenance() {
+        let store = Arc::new(MemoryStore::new(vec![test_support::sword(1, "First")]));
+        let dir = test_support::temp_dir("trade");
+        let providers = test_support::providers_with(store.clone(), &dir).await;
+        let armory: Arc<dyn ArmoryStore> = store.clone();
+
+        let (first, second, third) = (Chatter::named("first"), Chatter::named("Second"), Chatter::named("Third"));
+        assert!(providers.swords.transfer(Arc::clone(&armory), 1, &second, &third).await.is_err());
+        providers.swords.transfer(Arc::clone(&armory), 1, &first, &second).await.unwrap();
+        let sword = providers.swords.transfer(Arc::clone(&armory), 1, &second, &third).await.unwrap();
+        providers.swords.flush(test_support::TIMEOUT).await;
+        assert_eq!(sword.owner, "Third");
+        assert_eq!(sword.provenance().unwrap(), "Forged for First, later held by Second");
+        assert_eq!(store.swords().await[0]["owner"], "Third");
+        assert_eq!(store.swords().await[0]["history"].len(), 2);
+        assert_eq!(providers.swords.blade(&Chatter::named("third"), Some(1)).await.and_then(|s| s.id), Some(1));
+    }
+
+    #[tokio::test]
+    async fn exchanges_both_swords_or_neither() {
+        let store = Arc::new(MemoryStore::new(vec![test_support::sword(1, "First"), test_support::sword(2, "Second")]));
+        let dir = test_support::temp_dir("exchange");
+        let providers = test_support::providers_with(store.clone(), &dir).await;
+        let armory: Arc<dyn ArmoryStore> = store.clone();
+
+        let (first, second) = (Chatter::named("First"), Chatter::named("Second"));
+        assert!(providers.swords.exchange(Arc::clone(&armory), (1, &first), (1, &second)).await.is_err());
+        assert!(providers.swords.blade(&first, Some(1)).await.is_some());
+        providers.swords.exchange(Arc::clone(&armory), (1, &first), (2, &second)).await.unwrap();
+        assert!(providers.swords.blade(&second, Some(1)).await.is_some());
+        assert!(providers.swords.blade(&first, Some(2)).await.is_some());
+        providers.swords.flush(test_support::TIMEOUT).await;
+        let stored = store.swords().await;
+        assert_eq!((stored[0]["owner"].as_str(), stored[1]["owner"].as_str()), (Some("Second"), Some("First")));
+    }
+}
@@ -0,0 +1,176 @@
+use irc::client::prelude::{Command, Message};
+
+// Tags are parsed in full for commands to build on, so the types below allow fields nothing reads yet
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Badge {
+    pub name: String,
+    pub version: String
+}
+
+/// Emote id with the character ranges (inclusive) it occupies in the message text
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Emote {
+    pub id: String,
+    pub ranges: Vec<(usize, usize)>
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplyParent {
+    pub message_id: String,
+    pub user_id: Option<String>,
+    pub login: Option<String>,
+    pub display_name: Option<String>,
+    pub body: Option<String>
+}
+
+/// Twitch chat message with its IRCv3 tags parsed once.
+/// Tag values arrive unescaped from the irc crate, empty values are treated as missing
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct TwitchMessage {
+    pub channel: String,
+    pub text: String,
+    pub id: Option<String>,
+    pub user_id: Option<String>,
+    pub login: String,
+    pub display_name: String,
+    pub color: Option<String>,
+    pub badges: Vec<Badge>,
+    pub badge_info: Vec<Badge>,
+    pub emotes: Vec<Emote>,
+    pub moderator: bool,
+    pub subscriber: bool,
+    pub vip: bool,
+    pub first_msg: bool,
+    pub returning_chatter: bool,
+    pub reply_parent: Option<ReplyParent>,
+    pub bits: Option<u64>,
+    pub room_id: Option<String>,
+    pub tmi_sent_ts: Option<u64>
+}
+
+impl TwitchMessage {
+    /// Parse a PRIVMSG, anything else is None
+    pub fn parse(message: &Message) -> Option<Self> {
+        let (channel, text) = if let Command::PRIVMSG(channel, text) = &message.command {
+            (channel.clone(), text.clone())
+        } else {
+            return None;
+        };
+        let get = |name: &str| tag(message, name);
+        let flag = |name: &str| get(name).is_some_and(|v| v == "1");
+
+        let login = get("login")
+            .or(message.source_nickname().map(str::to_owned))
+            .unwrap_or("unknown".to_owned());
+        let reply_parent = get("reply-parent-msg-id").map(|message_id| ReplyParent {
+            message_id,
+            user_id: get("reply-parent-user-id"),
+            login: get("reply-parent-user-login"),
+            display_name: get("reply-parent-display-name"),
+            body: get("reply-parent-msg-body")
+        });
+
+        Some(Self {
+            channel,
+            text,
+            id: get("id"),
+            user_id: get("user-id"),
+            display_name: get("display-name").unwrap_or(login.clone()),
+            login,
+            color: get("color"),
+            badges: get("badges").map(|b| parse_badges(&b)).unwrap_or_default(),
+            badge_info: get("badge-info").map(|b| parse_badges(&b)).unwrap_or_default(),
+            emotes: get("emotes").map(|e| parse_emotes(&e)).unwrap_or_default(),
+            moderator: flag("mod"),
+            subscriber: flag("subscriber"),
+            vip: flag("vip"),
+            first_msg: flag("first-msg"),
+            returning_chatter: flag("returning-chatter"),
+            reply_parent,
+            bits: get("bits").and_then(|b| b.parse().ok()),
+            room_id: get("room-id"),
+            tmi_sent_ts: get("tmi-sent-ts").and_then(|t| t.parse().ok())
+        })
+    }
+
+    pub fn has_badge(&self, name: &str) -> bool {
+        self.badges.iter().any(|b| b.name == name)
+    }
+}
+
+/// Raw tag value, None if missing or empty
+pub fn tag(message: &Message, name: &str) -> Option<String> {
+    message.tags.iter().flatten()
+        .find(|t| t.0 == name)
+        .and_then(|t| t.1.clone())
+        .filter(|v| !v.is_empty())
+}
+
+fn parse_badges(value: &str) -> Vec<Badge> {
+    value.split(',')
+        .filter(|b| !b.is_empty())
+        .map(|b| {
+            let (name, version) = b.split_once('/').unwrap_or((b, ""));
+            Badge {
+                name: name.to_owned(),
+                version: version.to_owned()
+            }
+        })
+        .collect()
+}
+
+fn parse_emotes(value: &str) -> Vec<Emote> {
+    value.split('/')
+        .filter_map(|e| {
+            let (id, ranges) = e.split_once(':')?;
+            let ranges = ranges.split(',')
+                .filter_map(|r| {
+                    let (start, end) = r.split_once('-')?;
+                    Some((start.parse().ok()?, end.parse().ok()?))
+                })
+                .collect();
+            Some(Emote {
+                id: id.to_owned(),
+                ranges
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> TwitchMessage {
+        TwitchMessage::parse(&raw.parse::<Message>().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parses_tags() {
+        let message = parse("@badge-info=subscriber/8;badges=moderator/1,subscriber/6;color=;display-name=Some\\sOne;\
+            emotes=25:0-4,12-16/1902:6-10;first-msg=1;id=abc;mod=1;room-id=42;tmi-sent-ts=1700000000000;user-id=7 \
+            :someone!someone@someone.tmi.twitch.tv PRIVMSG #chan :Kappa Keepo Kappa");
+        assert_eq!(message.channel, "#chan");
+        assert_eq!(message.login, "someone");
+        assert_eq!(message.display_name, "Some One");
+        assert_eq!(message.color, None);
+        assert_eq!(message.user_id.as_deref(), Some("7"));
+        assert!(message.moderator && message.first_msg && !message.returning_chatter);
+        assert!(message.has_badge("subscriber"));
+        assert_eq!(message.badge_info, vec![Badge { name: "subscriber".to_owned(), version: "8".to_owned() }]);
+        assert_eq!(message.emotes[0], Emote { id: "25".to_owned(), ranges: vec![(0, 4), (12, 16)] });
+        assert_eq!(message.tmi_sent_ts, Some(1700000000000));
+    }
+
+    #[test]
+    fn falls_back_to_login() {
+        let message = parse(":someone!someone@someone.tmi.twitch.tv PRIVMSG #chan :hi");
+        assert_eq!(message.display_name, "someone");
+        assert_eq!(message.id, None);
+        assert!(message.badges.is_empty());
+    }
+}